@@ -1,8 +1,9 @@
 type Lineup = Vec<String>;
 
 use chess::*;
-use eval::Score;
-use minmax::ISUpdate;
+use eval::*;
+use history::*;
+use minmax::{ISUpdate, SearchStats};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WSMove {
@@ -10,6 +11,18 @@ pub struct WSMove {
     pub to: String,
 }
 
+/// The legal move from `from` to `to`, promoting to a queen if it is a promotion.
+pub fn find_ws_move(moves: &[ChessMove], from: &str, to: &str) -> Option<ChessMove> {
+    moves.iter().cloned().find(|m| {
+        let from2 = m.get_source().to_string();
+        let to2 = m.get_dest().to_string();
+        match m.get_promotion() {
+            Some(Piece::Queen) | None if from == from2 && to == to2 => true,
+            _ => false,
+        }
+    })
+}
+
 impl From<ChessMove> for WSMove {
     fn from(other: ChessMove) -> WSMove {
         let from = other.get_source().to_string();
@@ -18,19 +31,91 @@ impl From<ChessMove> for WSMove {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WSLine {
+    pub line: Vec<WSMove>,
+    pub value: Score,
+    pub mate: Option<i32>,
+}
+
+/// The counters of the search, see `SearchStats`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WSStats {
+    pub nodes: u64,
+    pub qnodes: u64,
+    pub nps: u64,
+    pub seldepth: i32,
+    pub tt_probes: u64,
+    pub tt_hits: u64,
+    pub tt_cutoffs: u64,
+    pub fail_highs: u64,
+    pub first_move_fail_highs: u64,
+    pub elapsed_ms: u64,
+}
+
+impl From<SearchStats> for WSStats {
+    fn from(stats: SearchStats) -> WSStats {
+        WSStats {
+            nodes: stats.nodes,
+            qnodes: stats.qnodes,
+            nps: stats.nps(),
+            seldepth: stats.seldepth,
+            tt_probes: stats.tt_probes,
+            tt_hits: stats.tt_hits,
+            tt_cutoffs: stats.tt_cutoffs,
+            fail_highs: stats.fail_highs,
+            first_move_fail_highs: stats.first_move_fail_highs,
+            elapsed_ms: stats.elapsed.as_secs() * 1000 + stats.elapsed.subsec_millis() as u64,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WSState {
     pub legal_moves: Vec<WSMove>,
     pub lineup: Lineup,
     pub best_line: Vec<WSMove>,
     pub best_value: Score,
+    /// Full moves until mate along the best line, negative when the side to move loses.
+    pub best_mate: Option<i32>,
+    /// The best lines of a MultiPV search, starting with `best_line`.
+    pub best_lines: Vec<WSLine>,
     pub side_to_move: &'static str,
+    pub status: &'static str,
+    pub hashfull: usize,
+    pub depth: i32,
+    /// Search statistics, missing before the first update of a search.
+    pub stats: Option<WSStats>,
+    /// Squares of pieces the opponent can win material against by capturing them.
+    pub hanging: Vec<String>,
+}
+
+/// Engine settings a client can change; missing fields are left as they are.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct WSConfig {
+    pub hash_mb: Option<usize>,
+    #[serde(default)]
+    pub clear_hash: bool,
+    pub threads: Option<usize>,
+    pub null_move: Option<bool>,
+    pub multi_pv: Option<usize>,
+    pub ponder: Option<bool>,
+    /// Plays weaker at levels below `MAX_SKILL_LEVEL`.
+    pub skill_level: Option<u32>,
+    /// Sets the skill level to play at about this rating.
+    pub elo: Option<u32>,
+    /// Centipawns a draw is worth less than zero to the side to move.
+    pub contempt: Option<Score>,
+    /// Limits the search of the current position to these moves; an empty list lifts
+    /// the limit. Playing a move lifts it as well.
+    pub search_moves: Option<Vec<WSMove>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum WSRMessage {
     Move { from: String, to: String },
     Reset,
+    Configure(WSConfig),
 }
 
 pub fn lineup(board: &Board) -> Lineup {
@@ -58,18 +143,62 @@ pub fn lineup(board: &Board) -> Lineup {
     lineup
 }
 
-pub fn compute_ws_state(board: Board, result: Option<ISUpdate>) -> WSState {
+/// Squares of pieces of either color that lose material to a capture, judged by SEE.
+fn hanging_pieces(board: &Board) -> Vec<String> {
+    let mut boards = vec![*board];
+    // Let the side to move pass to see which of its own pieces are attacked.
+    boards.extend(board.null_move());
+    let mut hanging = Vec::new();
+    for board in boards {
+        for cmove in MoveGen::new(board, true) {
+            if board.piece_on(cmove.get_dest()).is_some() && see(&board, cmove) > 0 {
+                hanging.push(cmove.get_dest().to_string());
+            }
+        }
+    }
+    hanging.sort();
+    hanging.dedup();
+    hanging
+}
+
+pub fn compute_ws_state(board: Board, history: &History, result: Option<ISUpdate>) -> WSState {
     let iterable = MoveGen::new(board, true);
-    let legal_moves = iterable.map(WSMove::from).collect();
+    let legal_moves: Vec<WSMove> = iterable.map(WSMove::from).collect();
+    let status = match quick_status(&board, legal_moves.len()) {
+        BoardStatus::Checkmate => "checkmate",
+        BoardStatus::Stalemate => "stalemate",
+        BoardStatus::Ongoing => match history.draw_reason(&board) {
+            Some(DrawReason::Repetition) => "repetition",
+            Some(DrawReason::FiftyMoves) => "fifty_moves",
+            None => "ongoing",
+        },
+    };
     let side_to_move = match board.side_to_move() {
         Color::White => "white",
         Color::Black => "black",
     };
-    let (best_line, best_value) = match result {
-        None => (Vec::new(), 0),
+    let (best_line, best_value, best_mate, best_lines, hashfull, depth, stats) = match result {
+        None => (Vec::new(), 0, None, Vec::new(), 0, 0, None),
         Some(update) => {
             let best_line = update.line.iter().map(|m| WSMove::from(*m)).collect();
-            (best_line, update.score)
+            let best_lines = update
+                .lines
+                .iter()
+                .map(|line| WSLine {
+                    line: line.line.iter().map(|m| WSMove::from(*m)).collect(),
+                    value: line.score,
+                    mate: line.mate,
+                })
+                .collect();
+            (
+                best_line,
+                update.score,
+                update.mate,
+                best_lines,
+                update.hashfull,
+                update.depth,
+                Some(WSStats::from(update.stats)),
+            )
         }
     };
     WSState {
@@ -77,6 +206,13 @@ pub fn compute_ws_state(board: Board, result: Option<ISUpdate>) -> WSState {
         lineup: lineup(&board),
         best_line,
         best_value,
+        best_mate,
+        best_lines,
         side_to_move,
+        status,
+        hashfull,
+        depth,
+        stats,
+        hanging: hanging_pieces(&board),
     }
 }
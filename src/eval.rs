@@ -7,6 +7,10 @@ pub type Score = i32;
 pub const WIN_SCORE: Score = 100000;
 pub const DRAW_SCORE: Score = 0;
 
+/// Mates are scored `WIN_SCORE` minus their distance in plies from the root, so scores
+/// within this many plies of `WIN_SCORE` are mates.
+const MAX_MATE_PLY: i32 = 1000;
+
 pub const MIN_SCORE: Score = -1000000;
 pub const MAX_SCORE: Score = 1000000;
 
@@ -119,6 +123,128 @@ fn movability_score(board: &Board, moves: &[ChessMove]) -> Score {
     movability
 }
 
+/// The piece captured by `cmove`, including pawns taken en passant.
+pub fn captured_piece(board: &Board, cmove: ChessMove) -> Option<Piece> {
+    match board.piece_on(cmove.get_dest()) {
+        Some(piece) => Some(piece),
+        None if board.piece_on(cmove.get_source()) == Some(Piece::Pawn)
+            && cmove.get_source().get_file() != cmove.get_dest().get_file() =>
+        {
+            Some(Piece::Pawn)
+        }
+        None => None,
+    }
+}
+
+/// Captures and promotions, the moves searched by quiescence search.
+pub fn is_tactical(board: &Board, cmove: ChessMove) -> bool {
+    cmove.get_promotion().is_some() || captured_piece(board, cmove).is_some()
+}
+
+/// The material gained by a capture or promotion, ignoring any recapture.
+pub fn tactical_gain(board: &Board, cmove: ChessMove) -> Score {
+    let captured = captured_piece(board, cmove).map(piece_score).unwrap_or(0);
+    let promoted = cmove
+        .get_promotion()
+        .map(|piece| piece_score(piece) - piece_score(Piece::Pawn))
+        .unwrap_or(0);
+    captured + promoted
+}
+
+/// Most valuable victim, least valuable attacker ordering key.
+pub fn mvv_lva_score(board: &Board, cmove: ChessMove) -> Score {
+    let attacker = board
+        .piece_on(cmove.get_source())
+        .map(piece_score)
+        .unwrap_or(0);
+    tactical_gain(board, cmove) * 10 - attacker
+}
+
+/// Pieces of both colors that attack `sq` through the pieces in `occupied`.
+fn attackers_to(board: &Board, sq: Square, occupied: BitBoard) -> BitBoard {
+    let white_pawns = board.pieces(Piece::Pawn) & board.color_combined(Color::White);
+    let black_pawns = board.pieces(Piece::Pawn) & board.color_combined(Color::Black);
+    let queens = board.pieces(Piece::Queen);
+    let diagonal = board.pieces(Piece::Bishop) | queens;
+    let straight = board.pieces(Piece::Rook) | queens;
+    let attackers = get_pawn_attacks(sq, Color::Black, white_pawns)
+        | get_pawn_attacks(sq, Color::White, black_pawns)
+        | (get_knight_moves(sq) & board.pieces(Piece::Knight))
+        | (get_king_moves(sq) & board.pieces(Piece::King))
+        | (get_bishop_moves(sq, occupied) & diagonal)
+        | (get_rook_moves(sq, occupied) & straight);
+    attackers & occupied
+}
+
+fn least_valuable_attacker(board: &Board, attackers: BitBoard) -> Option<(Piece, Square)> {
+    ALL_PIECES.iter().find_map(|&piece| {
+        let candidates = attackers & board.pieces(piece);
+        if candidates == EMPTY {
+            None
+        } else {
+            Some((piece, candidates.to_square()))
+        }
+    })
+}
+
+/// Static exchange evaluation: the material the side to move wins by playing `cmove`
+/// when both sides keep recapturing on its destination square with their least valuable
+/// piece, each stopping as soon as continuing would lose material.
+///
+/// Sliders behind the capturing pieces join in as the exchange goes on. Pawns reaching
+/// the last rank promote to queens, and pins are ignored.
+pub fn see(board: &Board, cmove: ChessMove) -> Score {
+    let source = cmove.get_source();
+    let dest = cmove.get_dest();
+    let mut on_dest = match board.piece_on(source) {
+        Some(piece) => cmove.get_promotion().unwrap_or(piece),
+        None => return 0,
+    };
+    let mut occupied = board.combined() ^ BitBoard::from_square(source);
+    if board.piece_on(dest).is_none() && captured_piece(board, cmove).is_some() {
+        // En passant: the captured pawn is beside the destination, not on it.
+        occupied ^= BitBoard::set(source.get_rank(), dest.get_file());
+    }
+    let last_ranks = get_rank(Rank::First) | get_rank(Rank::Eighth);
+    let promotes = last_ranks & BitBoard::from_square(dest) != EMPTY;
+    let promotion_gain = piece_score(Piece::Queen) - piece_score(Piece::Pawn);
+
+    // gains[i] is the material won by the side making capture i if the exchange ended there.
+    let mut gains = [0; 32];
+    gains[0] = tactical_gain(board, cmove);
+    let mut num_captures = 1;
+    let mut side = !board.side_to_move();
+    while num_captures < gains.len() {
+        let attackers = attackers_to(board, dest, occupied);
+        let (piece, square) =
+            match least_valuable_attacker(board, attackers & board.color_combined(side)) {
+                Some(attacker) => attacker,
+                None => break,
+            };
+        if piece == Piece::King
+            && attackers & board.color_combined(!side) & !BitBoard::from_square(square) != EMPTY
+        {
+            // The king cannot recapture on a defended square.
+            break;
+        }
+        let mut gain = piece_score(on_dest);
+        on_dest = piece;
+        if piece == Piece::Pawn && promotes {
+            gain += promotion_gain;
+            on_dest = Piece::Queen;
+        }
+        gains[num_captures] = gain - gains[num_captures - 1];
+        num_captures += 1;
+        occupied ^= BitBoard::from_square(square);
+        side = !side;
+    }
+    // Each side may stop capturing when that is better for it.
+    for i in (1..num_captures).rev() {
+        gains[i - 1] = -max(-gains[i - 1], gains[i]);
+    }
+    gains[0]
+}
+
 pub fn quick_status(board: &Board, num_moves: usize) -> BoardStatus {
     if num_moves == 0 {
         if board.checkers().popcnt() > 0 {
@@ -131,26 +257,44 @@ pub fn quick_status(board: &Board, num_moves: usize) -> BoardStatus {
     }
 }
 
-pub fn fast_board_score(board: &Board, depth: i32) -> Score {
-    let active_color = board.side_to_move();
+/// The score for the side to move of giving mate `ply` plies from the root.
+pub fn mate_in(ply: i32) -> Score {
+    WIN_SCORE - ply
+}
+
+/// The score for the side to move of being mated `ply` plies from the root.
+pub fn mated_in(ply: i32) -> Score {
+    -WIN_SCORE + ply
+}
+
+pub fn is_mate_score(score: Score) -> bool {
+    score.abs() > WIN_SCORE - MAX_MATE_PLY
+}
+
+/// Full moves until mate for a mate score, negative when the side to move gets mated.
+pub fn mate_distance(score: Score) -> Option<i32> {
+    if !is_mate_score(score) {
+        None
+    } else if score > 0 {
+        Some((WIN_SCORE - score + 1) / 2)
+    } else {
+        Some(-(WIN_SCORE + score) / 2)
+    }
+}
+
+pub fn fast_board_score(board: &Board) -> Score {
     let material = material_score(&board, Color::White) - material_score(&board, Color::Black);
     let position = position_score(&board, Color::White) - position_score(&board, Color::Black);
-    let mut score = material + position;
-    if board.checkers().popcnt() > 0 {
-        if active_color == Color::White {
-            score -= WIN_SCORE + depth;
-        } else {
-            score += WIN_SCORE + depth;
-        }
-    }
-    score
+    material + position
 }
 
-pub fn board_score(board: &Board, moves: &[ChessMove], depth: i32) -> Score {
+/// The static score of `board` from white's point of view, reached `ply` plies from the
+/// root of the search.
+pub fn board_score(board: &Board, moves: &[ChessMove], ply: i32) -> Score {
     let active_color = board.side_to_move();
     match quick_status(board, moves.len()) {
-        BoardStatus::Checkmate if active_color == Color::White => -(WIN_SCORE + depth),
-        BoardStatus::Checkmate => WIN_SCORE + depth,
+        BoardStatus::Checkmate if active_color == Color::White => mated_in(ply),
+        BoardStatus::Checkmate => -mated_in(ply),
         BoardStatus::Stalemate => DRAW_SCORE,
         BoardStatus::Ongoing => {
             let material =
@@ -164,3 +308,56 @@ pub fn board_score(board: &Board, moves: &[ChessMove], depth: i32) -> Score {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::parse_coord_move;
+
+    fn see_of(fen: &str, cmove: &str) -> Score {
+        let board = Board::from_fen(fen.to_string()).unwrap();
+        see(&board, parse_coord_move(&board, cmove).unwrap())
+    }
+
+    #[test]
+    fn see_wins_undefended_pawn() {
+        assert_eq!(
+            see_of("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5"),
+            100
+        );
+    }
+
+    #[test]
+    fn see_loses_knight_for_defended_pawn() {
+        let fen = "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1";
+        assert_eq!(see_of(fen, "d3e5"), -220);
+    }
+
+    #[test]
+    fn see_counts_xrays_on_file() {
+        assert_eq!(
+            see_of("3r2k1/3r4/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2d5"),
+            -400
+        );
+    }
+
+    #[test]
+    fn see_en_passant() {
+        assert_eq!(see_of("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), 100);
+    }
+
+    #[test]
+    fn see_capture_promotion() {
+        assert_eq!(see_of("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7b8q"), 1300);
+    }
+
+    #[test]
+    fn see_king_cannot_recapture_defended_square() {
+        assert_eq!(see_of("8/8/3k4/3r4/4P3/8/8/3RK3 w - - 0 1", "e4d5"), 500);
+    }
+
+    #[test]
+    fn see_quiet_move_onto_attacked_square() {
+        assert_eq!(see_of("4k3/8/8/8/1p6/8/8/2Q1K3 w - - 0 1", "c1c3"), -900);
+    }
+}
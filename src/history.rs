@@ -0,0 +1,90 @@
+use chess::*;
+
+use eval::*;
+
+/// Number of halfmoves without capture or pawn move after which the game is drawn.
+const FIFTY_MOVES: u32 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DrawReason {
+    Repetition,
+    FiftyMoves,
+}
+
+/// The positions leading up to the current one, which the `Board` itself does not keep.
+///
+/// Used by the game loops to adjudicate draws and by the search to score repeated
+/// positions along its path.
+#[derive(Clone, Debug)]
+pub struct History {
+    /// Hash and halfmove clock of every position, the current one last.
+    entries: Vec<(u64, u32)>,
+}
+
+impl History {
+    pub fn new(board: &Board, halfmove_clock: u32) -> History {
+        History {
+            entries: vec![(board.get_hash(), halfmove_clock)],
+        }
+    }
+
+    /// Records `cmove` played on `board`, which must be the current position.
+    pub fn push(&mut self, board: &Board, cmove: ChessMove) {
+        let irreversible = board.piece_on(cmove.get_source()) == Some(Piece::Pawn)
+            || captured_piece(board, cmove).is_some();
+        let halfmove_clock = if irreversible {
+            0
+        } else {
+            self.halfmove_clock() + 1
+        };
+        let hash = board.make_move(cmove).get_hash();
+        self.entries.push((hash, halfmove_clock));
+    }
+
+    /// Records a null move; like a capture it ends the window for repetitions.
+    pub fn push_null(&mut self, board: &Board) {
+        self.entries.push((board.get_hash(), 0));
+    }
+
+    pub fn pop(&mut self) {
+        self.entries.pop();
+    }
+
+    pub fn halfmove_clock(&self) -> u32 {
+        self.entries.last().map_or(0, |&(_, clock)| clock)
+    }
+
+    /// How often the current position occurred before.
+    pub fn repetitions(&self) -> usize {
+        let (hash, clock) = match self.entries.last() {
+            Some(&entry) => entry,
+            None => return 0,
+        };
+        self.entries
+            .iter()
+            .rev()
+            .take(clock as usize + 1)
+            .skip(2)
+            .step_by(2)
+            .filter(|&&(other, _)| other == hash)
+            .count()
+    }
+
+    /// Whether the search should score the current position as a draw. Unlike the
+    /// rules of the game, a single repetition already counts.
+    pub fn is_search_draw(&self, board: &Board) -> bool {
+        self.repetitions() > 0
+            || self.halfmove_clock() >= FIFTY_MOVES && board.status() != BoardStatus::Checkmate
+    }
+
+    /// The draw that can be claimed in the current position, if any.
+    pub fn draw_reason(&self, board: &Board) -> Option<DrawReason> {
+        if self.repetitions() >= 2 {
+            Some(DrawReason::Repetition)
+        } else if self.halfmove_clock() >= FIFTY_MOVES && board.status() != BoardStatus::Checkmate {
+            Some(DrawReason::FiftyMoves)
+        } else {
+            None
+        }
+    }
+}
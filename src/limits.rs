@@ -0,0 +1,83 @@
+use std::cmp::min;
+use std::time::{Duration, Instant};
+
+use chess::ChessMove;
+
+pub const MAX_DEPTH: i32 = 99;
+
+/// Time reserved for communication with the GUI on every move.
+const MOVE_OVERHEAD: Duration = Duration::from_millis(30);
+/// Number of moves the remaining clock time is divided over when the GUI does not say.
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+/// How many times the allocated time a single iteration may run over.
+const HARD_LIMIT_FACTOR: u32 = 4;
+
+/// The conditions under which a search reports its final result.
+///
+/// Clock times are those of the side to move. Without any limit the search runs until
+/// `MAX_DEPTH` is reached or it is stopped.
+#[derive(Clone, Default)]
+pub struct SearchLimits {
+    pub depth: Option<i32>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+    pub time_left: Option<Duration>,
+    pub increment: Duration,
+    pub moves_to_go: Option<u32>,
+    /// Keep the result until the search is stopped, even when `depth` is reached.
+    pub infinite: bool,
+    /// The root moves to choose from; all legal moves when empty.
+    pub search_moves: Vec<ChessMove>,
+    /// Search on the opponent's time: the clock only starts at the ponder hit, and the
+    /// result is kept until then like in an infinite search.
+    pub ponder: bool,
+}
+
+impl SearchLimits {
+    pub fn max_depth(&self) -> i32 {
+        min(self.depth.unwrap_or(MAX_DEPTH), MAX_DEPTH)
+    }
+
+    pub fn deadlines(&self, start: Instant) -> Deadlines {
+        let (soft, hard) = if self.infinite {
+            (None, None)
+        } else if let Some(movetime) = self.movetime {
+            (Some(movetime), Some(movetime))
+        } else if let Some(time_left) = self.time_left {
+            let available = time_left
+                .checked_sub(MOVE_OVERHEAD)
+                .unwrap_or(Duration::from_millis(0));
+            let moves_to_go = self.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+            let hard = min(
+                (available / moves_to_go + self.increment) * HARD_LIMIT_FACTOR,
+                available / 2,
+            );
+            let soft = min(available / moves_to_go + self.increment, hard);
+            (Some(soft), Some(hard))
+        } else {
+            (None, None)
+        };
+        Deadlines {
+            soft: soft.map(|soft| start + soft),
+            hard: hard.map(|hard| start + hard),
+        }
+    }
+}
+
+/// No new iteration is started after the soft deadline and the search is aborted at the
+/// hard deadline.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadlines {
+    pub soft: Option<Instant>,
+    pub hard: Option<Instant>,
+}
+
+impl Deadlines {
+    pub fn soft_passed(&self) -> bool {
+        self.soft.map_or(false, |soft| Instant::now() >= soft)
+    }
+
+    pub fn hard_passed(&self) -> bool {
+        self.hard.map_or(false, |hard| Instant::now() >= hard)
+    }
+}
@@ -5,11 +5,6 @@ extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
 
-extern crate atomic_option;
-
-#[macro_use]
-extern crate lazy_static;
-
 extern crate futures;
 extern crate tokio;
 extern crate tokio_tungstenite;
@@ -17,14 +12,25 @@ extern crate tungstenite;
 
 mod client;
 mod eval;
+mod history;
+mod limits;
 mod minmax;
+mod ordering;
+mod skill;
 mod ttable;
+mod uci;
 mod utils;
+mod xboard;
 
+use std::cmp::{max, min};
+use std::env;
 use std::io::{Error, ErrorKind};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
 
 use futures::future::{err, loop_fn, ok, Either, Loop};
 use futures::stream::Stream;
+use futures::sync::mpsc::{unbounded, UnboundedSender};
 use tokio::net::TcpListener;
 use tokio::prelude::*;
 use tungstenite::protocol::Message;
@@ -35,80 +41,228 @@ use chess::*;
 
 use client::*;
 use eval::*;
+use history::*;
+use limits::*;
 use minmax::*;
+use skill::*;
 use ttable::*;
 use utils::*;
 
-struct GameState<St, Si> {
+/// The transposition table shared by all connections, replaced when it is resized.
+type SharedTable = Arc<RwLock<Arc<TTable>>>;
+
+struct GameState<St> {
     board: Board,
+    history: History,
+    options: SearchOptions,
+    /// Root moves the next searches are limited to, empty for all moves.
+    search_moves: Vec<ChessMove>,
+    /// The opponent's reply the running search ponders on, if it is pondering.
+    ponder_move: Option<ChessMove>,
+    ttable: SharedTable,
     incoming: St,
-    outgoing: Si,
+    outgoing: UnboundedSender<Message>,
     search: Option<InfiniteSearch>,
 }
 
-lazy_static! {
-    static ref TTABLE: TTable = { TTable::new(100 * 1024 * 1024) };
+fn send_state(
+    outgoing: &UnboundedSender<Message>,
+    board: Board,
+    history: &History,
+    update: Option<ISUpdate>,
+) -> bool {
+    let state = compute_ws_state(board, history, update);
+    let msg = serde_json::to_string(&state).unwrap();
+    outgoing.unbounded_send(Message::Text(msg)).is_ok()
+}
+
+fn start_search(
+    outgoing: &UnboundedSender<Message>,
+    board: Board,
+    history: &History,
+    options: &SearchOptions,
+    search_moves: &[ChessMove],
+    ponder: bool,
+    ttable: &SharedTable,
+) -> InfiniteSearch {
+    let sender = outgoing.clone();
+    let start = Instant::now();
+    let search_history = history.clone();
+    let history = history.clone();
+    let limits = SearchLimits {
+        search_moves: search_moves.to_vec(),
+        ponder,
+        ..SearchLimits::default()
+    };
+    let options = options.clone();
+    let ttable = ttable.read().unwrap().clone();
+    let limited = is_limited(options.skill_level);
+    InfiniteSearch::start(ttable, board, search_history, limits, options, move |update| {
+        // At a limited skill level only the final update has the move picked to play, and
+        // the analysis before it would give away the better moves.
+        if update.done != limited || update.pondering {
+            return;
+        }
+        let elapsed = start.elapsed();
+        println!(
+            "Received depth: {}, score: {} in {}s {}ms",
+            update.depth,
+            update.score,
+            elapsed.as_secs(),
+            elapsed.subsec_millis()
+        );
+        send_state(&sender, board, &history, Some(update));
+    })
+}
+
+/// Stops the running search, which is restarted for the new state by the next step.
+fn stop_search(search: &mut Option<InfiniteSearch>, ponder_move: &mut Option<ChessMove>) {
+    if let Some(search) = search.take() {
+        search.cancel();
+    }
+    *ponder_move = None;
 }
 
-fn step<St, Si>(
+fn step<St>(
     GameState {
         mut board,
+        mut history,
+        mut options,
+        mut search_moves,
+        mut ponder_move,
+        ttable,
         incoming,
         outgoing,
         search,
-    }: GameState<St, Si>,
-) -> impl Future<Item = Loop<(), GameState<St, Si>>, Error = String>
+    }: GameState<St>,
+) -> impl Future<Item = Loop<(), GameState<St>>, Error = String>
 where
     St: Stream<Item = Message, Error = String>,
-    Si: Sink<SinkItem = Message, SinkError = String>,
 {
     let moves = generate_moves(&board);
-    let state = compute_ws_state(board, None);
-    let msg = serde_json::to_string(&state).unwrap();
-    outgoing.send(Message::Text(msg)).and_then(move |outgoing| {
-        incoming
-            .into_future()
-            .map_err(move |(err, _incoming)| err)
-            .and_then(move |(ws_msg, incoming)| match ws_msg {
-                Some(Message::Text(text_msg)) => match serde_json::from_str(&text_msg) {
-                    Ok(msg) => {
-                        println!("Received message {:?}", msg);
-                        match msg {
-                            WSRMessage::Reset => {
-                                board = Board::default();
-                            }
-                            WSRMessage::Move { from, to } => {
-                                let option_cmove = moves.iter().cloned().find(|m| {
-                                    let from2 = m.get_source().to_string();
-                                    let to2 = m.get_dest().to_string();
-                                    match m.get_promotion() {
-                                        Some(Piece::Queen) | None if from == from2 && to == to2 => {
-                                            true
-                                        }
-                                        _ => false,
-                                    }
-                                });
-                                match option_cmove {
-                                    None => return Err("Invalid move".to_string()),
-                                    Some(cmove) => {
-                                        board = board.make_move(cmove);
+    let mut search = match search {
+        Some(search) => Some(search),
+        None if send_state(&outgoing, board, &history, None) => Some(start_search(
+            &outgoing,
+            board,
+            &history,
+            &options,
+            &search_moves,
+            false,
+            &ttable,
+        )),
+        None => None,
+    };
+    incoming
+        .into_future()
+        .map_err(move |(err, _incoming)| err)
+        .and_then(move |(ws_msg, incoming)| match ws_msg {
+            Some(Message::Text(text_msg)) => match serde_json::from_str(&text_msg) {
+                Ok(msg) => {
+                    println!("Received message {:?}", msg);
+                    match msg {
+                        WSRMessage::Reset => {
+                            stop_search(&mut search, &mut ponder_move);
+                            board = Board::default();
+                            history = History::new(&board, 0);
+                            search_moves.clear();
+                        }
+                        WSRMessage::Move { from, to } => match find_ws_move(&moves, &from, &to) {
+                            None => return Err("Invalid move".to_string()),
+                            Some(cmove) => {
+                                let ponder_hit = ponder_move.take() == Some(cmove);
+                                let last = if ponder_hit {
+                                    None
+                                } else {
+                                    search.take().and_then(|search| search.cancel())
+                                };
+                                history.push(&board, cmove);
+                                board = board.make_move(cmove);
+                                search_moves.clear();
+                                if ponder_hit {
+                                    // The search already runs on this position.
+                                    send_state(&outgoing, board, &history, None);
+                                    if let Some(ref search) = search {
+                                        search.ponder_hit();
                                     }
+                                } else if let Some(reply) = last
+                                    .filter(|_| options.ponder)
+                                    .filter(|last| last.line.first() == Some(&cmove))
+                                    .and_then(|last| last.line.get(1).cloned())
+                                {
+                                    // The engine's move was played: search the position
+                                    // after the reply it expects until the opponent moves.
+                                    send_state(&outgoing, board, &history, None);
+                                    let mut ponder_history = history.clone();
+                                    ponder_history.push(&board, reply);
+                                    search = Some(start_search(
+                                        &outgoing,
+                                        board.make_move(reply),
+                                        &ponder_history,
+                                        &options,
+                                        &[],
+                                        true,
+                                        &ttable,
+                                    ));
+                                    ponder_move = Some(reply);
                                 }
                             }
+                        },
+                        WSRMessage::Configure(config) => {
+                            stop_search(&mut search, &mut ponder_move);
+                            if let Some(threads) = config.threads {
+                                options.threads = min(max(1, threads), MAX_THREADS);
+                            }
+                            if let Some(hash_mb) = config.hash_mb {
+                                let hash_mb = min(max(1, hash_mb), MAX_HASH_MB);
+                                resize_table(&mut ttable.write().unwrap(), hash_mb * MB);
+                            }
+                            if let Some(null_move) = config.null_move {
+                                options.null_move = null_move;
+                            }
+                            if let Some(multi_pv) = config.multi_pv {
+                                options.multi_pv = max(1, multi_pv);
+                            }
+                            if let Some(ponder) = config.ponder {
+                                options.ponder = ponder;
+                            }
+                            if let Some(level) = config.skill_level {
+                                options.skill_level = min(level, MAX_SKILL_LEVEL);
+                            }
+                            if let Some(elo) = config.elo {
+                                options.skill_level = skill_level_for_elo(elo);
+                            }
+                            if let Some(contempt) = config.contempt {
+                                options.contempt = min(max(-MAX_CONTEMPT, contempt), MAX_CONTEMPT);
+                            }
+                            if let Some(ws_moves) = config.search_moves {
+                                search_moves = ws_moves
+                                    .iter()
+                                    .filter_map(|m| find_ws_move(&moves, &m.from, &m.to))
+                                    .collect();
+                            }
+                            if config.clear_hash {
+                                ttable.read().unwrap().clear();
+                            }
                         }
-                        Ok(Loop::Continue(GameState {
-                            board,
-                            incoming,
-                            outgoing,
-                            search,
-                        }))
                     }
-                    Err(_serde_err) => Err("Invalid message".to_string()),
-                },
-                None => Ok(Loop::Break(())),
-                _ => Err("Invalid message".to_string()),
-            })
-    })
+                    Ok(Loop::Continue(GameState {
+                        board,
+                        history,
+                        options,
+                        search_moves,
+                        ponder_move,
+                        ttable,
+                        incoming,
+                        outgoing,
+                        search,
+                    }))
+                }
+                Err(_serde_err) => Err("Invalid message".to_string()),
+            },
+            None => Ok(Loop::Break(())),
+            _ => Err("Invalid message".to_string()),
+        })
 }
 
 fn main() {
@@ -118,6 +272,37 @@ fn main() {
     // let elapsed = start.elapsed();
     // println!("Elapsed: {}s {}m, N: {}", elapsed.as_secs(), elapsed.subsec_millis(), n);
 
+    let mut mode = None;
+    let mut options = SearchOptions::default();
+    let mut hash_mb = DEFAULT_HASH_MB;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "uci" | "xboard" => mode = Some(arg),
+            "--threads" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(threads) => options.threads = min(max(1, threads), MAX_THREADS),
+                None => panic!("--threads expects a number"),
+            },
+            "--no-null-move" => options.null_move = false,
+            "--root-search" => match args.next().and_then(|n| RootSearch::from_name(&n)) {
+                Some(strategy) => options.root_search = strategy,
+                None => panic!("--root-search expects Aspiration, MTDf or FullWindow"),
+            },
+            "--hash" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(megabytes) => hash_mb = min(max(1, megabytes), MAX_HASH_MB),
+                None => panic!("--hash expects a size in MB"),
+            },
+            _ => panic!("Unknown argument: {}", arg),
+        }
+    }
+    let ttable = Arc::new(TTable::new(hash_mb * MB));
+    match mode.as_ref().map(|s| s.as_str()) {
+        Some("uci") => return uci::run(ttable, options),
+        Some("xboard") => return xboard::run(ttable, options),
+        _ => (),
+    }
+    let ttable = Arc::new(RwLock::new(ttable));
+
     let addr = "127.0.0.1:3012";
     let addr = addr.parse().unwrap();
 
@@ -126,6 +311,8 @@ fn main() {
     println!("Listening on: {}", addr);
 
     let srv = socket.incoming().for_each(move |socket| {
+        let options = options.clone();
+        let ttable = ttable.clone();
         let addr = socket
             .peer_addr()
             .expect("connected streams should have a peer address");
@@ -136,10 +323,22 @@ fn main() {
                 let stream = stream.map_err(|err| err.to_string());
                 let sink = sink.sink_map_err(|err| err.to_string());
 
+                // Search updates arrive from worker threads, so all outgoing messages go
+                // through a channel that is forwarded to the socket by a separate task.
+                let (sender, receiver) = unbounded();
+                let receiver = receiver.map_err(|()| "Channel closed".to_string());
+                tokio::spawn(sink.send_all(receiver).then(|_| Ok(())));
+
+                let board = Board::default();
                 let state = GameState {
-                    board: Board::default(),
+                    board,
+                    history: History::new(&board, 0),
+                    options,
+                    search_moves: Vec::new(),
+                    ponder_move: None,
+                    ttable,
                     incoming: stream,
-                    outgoing: sink,
+                    outgoing: sender,
                     search: None,
                 };
 
@@ -158,63 +357,5 @@ fn main() {
     });
 
     tokio::runtime::run(srv.map_err(|_e| ()));
-
-    // listen("127.0.0.1:3012", handle_connection)
-    //     .unwrap_or_else(|err| panic!("Cannot listen to port 3012: {}", err));
 }
 
-// fn handle_connection(out: Sender) -> impl Handler {
-//     let board_cell = Cell::new(Board::default());
-//     let moves_cell = RefCell::new(([ChessMove::default(); 256], 0));
-//     let is_cell: Cell<Option<InfiniteSearch>> = Cell::new(None);
-//     move |raw_msg| {
-//         match raw_msg {
-//             Message::Binary(_) => out.close(CloseCode::Error),
-//             Message::Text(text) => {
-//                 match serde_json::from_str(&text) {
-//                     Ok(msg) => {
-//                         if let Some(is) = is_cell.take() {
-//                             is.join();
-//                         }
-//                         let mut board = board_cell.get();
-//                         let (ref mut moves, ref mut num_moves) = *moves_cell.borrow_mut();
-//                         match msg {
-//                             WSRMessage::Reset => {
-//                                 board_cell.set(Board::default());
-//                             },
-//                             WSRMessage::Move {from, to} => {
-//                                 for i in 0..*num_moves {
-//                                     let m = moves[i];
-//                                     match m.get_promotion() {
-//                                         None | Some(Piece::Queen) if (m.get_source().to_string() == from && m.get_dest().to_string() == to) => {
-//                                             board = board.make_move(m);
-//                                         },
-//                                         _ => (),
-//                                     };
-//                                 }
-//                             }
-//                         };
-//                         let sender = out.clone();
-//                         let start = Instant::now();
-//                         let is = InfiniteSearch::start(&TTABLE, board, 99, move |update| {
-//                             let elapsed = start.elapsed();
-//                             println!("Received depth: {}, score: {} in {}s {}ms", update.depth, update.score, elapsed.as_secs(), elapsed.subsec_millis());
-//                             let state = compute_ws_state(board, Some(update));
-//                             let msg = serde_json::to_string(&state).unwrap();
-//                             if sender.send(Message::Text(msg)).is_err() {
-//                                 sender.close(CloseCode::Error).unwrap();
-//                             }
-//                         });
-//                         is_cell.set(Some(is));
-//                         *num_moves = board.enumerate_moves(moves);
-//                         board_cell.set(board);
-//                         Ok(())
-//                     },
-//                     Err(_) => {
-//                         out.close(CloseCode::Error)
-//                     },
-//                 }
-//             }
-//         }
-//     }
-// }
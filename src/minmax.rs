@@ -1,20 +1,66 @@
+use std::cell::Cell;
 use std::cmp::{max, min};
 use std::mem;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::sync::mpsc::{channel, Sender};
 use std::sync::Arc;
 
 use std::marker::Send;
 
-use std::thread::{spawn, JoinHandle};
+use std::thread::{sleep, spawn, JoinHandle};
 
 use chess::*;
 use eval::*;
+use history::*;
+use limits::*;
+use ordering::*;
+use skill::*;
 use ttable::*;
 
+/// The most threads the front-ends accept for one search.
+pub const MAX_THREADS: usize = 256;
+/// The largest contempt the front-ends accept, in centipawns either way.
+pub const MAX_CONTEMPT: Score = 500;
+
+/// Nodes a thread searches between checks of the shared counters.
+const NODE_BATCH: usize = 1024;
+
+/// Null move pruning is tried from this depth on.
+const NULL_MOVE_MIN_DEPTH: i32 = 3;
+/// Depth reduction of the null move search, growing by one every six plies.
+const NULL_MOVE_REDUCTION: i32 = 2;
+/// Null move cutoffs from this depth on are verified by a reduced normal search.
+const NULL_MOVE_VERIFY_DEPTH: i32 = 6;
+
+/// Late move reductions start at this depth and move index.
+const LMR_MIN_DEPTH: i32 = 3;
+const LMR_MIN_MOVES: usize = 3;
+
+/// Singular extensions are tried from this depth on, for TT moves whose value beats
+/// every other move by this margin per ply of depth.
+const SINGULAR_MIN_DEPTH: i32 = 8;
+const SINGULAR_MARGIN: Score = 2;
+
+/// Principal variation nodes without a TT move from this depth on first search at a
+/// depth this much lower to find a move to try first.
+const IID_MIN_DEPTH: i32 = 5;
+const IID_REDUCTION: i32 = 2;
+/// Other nodes without a TT move from this depth on are searched one ply shallower.
+const IIR_MIN_DEPTH: i32 = 4;
+
+/// Static pruning near the leaves applies up to these depths. Its margins, scaled by the
+/// remaining depth, are part of `SearchOptions`.
+const FUTILITY_MAX_DEPTH: i32 = 3;
+const REVERSE_FUTILITY_MAX_DEPTH: i32 = 6;
+const RAZOR_MAX_DEPTH: i32 = 2;
+
+/// Captures that cannot raise the static score to within this margin of alpha are
+/// skipped by quiescence search.
+const DELTA_MARGIN: Score = 200;
+
 pub struct AlphaBetaResult {
     pub line: Vec<ChessMove>,
     pub score: Score,
@@ -32,70 +78,284 @@ impl ABResult {
                 value: ValueInfo::Exact(best_value),
                 depth,
                 ..
-            }
-                if depth == min_depth =>
-            {
-                Some(ABResult {
-                    best_value,
-                    best_move: Some(entry.best_move),
-                })
-            }
+            } if depth == min_depth => Some(ABResult {
+                best_value,
+                best_move: Some(entry.best_move),
+            }),
             _ => None,
         }
     }
 }
 
+/// Counters of one search, reported with every update. Apart from `nodes`, which counts
+/// the nodes of all threads, they describe the main thread only.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SearchStats {
+    pub nodes: u64,
+    /// Nodes searched by the quiescence search.
+    pub qnodes: u64,
+    /// Deepest ply reached, including extensions and the quiescence search.
+    pub seldepth: i32,
+    pub tt_probes: u64,
+    pub tt_hits: u64,
+    /// Probes that decided a node without searching it.
+    pub tt_cutoffs: u64,
+    /// Beta cutoffs, and those among them caused by the first move searched.
+    pub fail_highs: u64,
+    pub first_move_fail_highs: u64,
+    pub elapsed: Duration,
+}
+
+impl SearchStats {
+    pub fn nps(&self) -> u64 {
+        let millis = self.elapsed.as_secs() * 1000 + self.elapsed.subsec_millis() as u64;
+        self.nodes * 1000 / max(1, millis)
+    }
+
+    /// Percentage of the table probes that found an entry.
+    pub fn tt_hit_rate(&self) -> f64 {
+        percentage(self.tt_hits, self.tt_probes)
+    }
+
+    /// Percentage of the beta cutoffs caused by the first move, a measure of the move
+    /// ordering.
+    pub fn first_move_fail_high_rate(&self) -> f64 {
+        percentage(self.first_move_fail_highs, self.fail_highs)
+    }
+}
+
+fn percentage(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        100.0 * part as f64 / total as f64
+    }
+}
+
+/// One of the lines reported in MultiPV mode.
+#[derive(Clone)]
+pub struct PVLine {
+    pub line: Vec<ChessMove>,
+    pub score: Score,
+    pub mate: Option<i32>,
+}
+
+#[derive(Clone)]
 pub struct ISUpdate {
+    /// The best line, the same as the first of `lines`.
     pub line: Vec<ChessMove>,
     pub score: Score,
+    /// Full moves until mate if `score` is a mate score, negative when getting mated.
+    pub mate: Option<i32>,
+    /// The best lines starting with different root moves, best first. Holds more than
+    /// one line only when `SearchOptions::multi_pv` asks for it.
+    pub lines: Vec<PVLine>,
     pub depth: i32,
+    pub stats: SearchStats,
+    /// Permille of the transposition table filled by this search.
+    pub hashfull: usize,
+    /// Set while the search ponders, before the opponent made the expected move.
+    pub pondering: bool,
+    /// Set on the last update of a search, which repeats the best line found.
+    pub done: bool,
+}
+
+/// How the root of each iteration is searched.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RootSearch {
+    /// A narrow window around the previous value, widened when the value falls outside.
+    Aspiration,
+    /// Null window searches converging on the value (MTD(f)).
+    Mtdf,
+    /// A single search with an unbounded window.
+    FullWindow,
+}
+
+impl RootSearch {
+    pub const ALL: [RootSearch; 3] = [
+        RootSearch::Aspiration,
+        RootSearch::Mtdf,
+        RootSearch::FullWindow,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RootSearch::Aspiration => "Aspiration",
+            RootSearch::Mtdf => "MTDf",
+            RootSearch::FullWindow => "FullWindow",
+        }
+    }
+
+    /// The strategy called `name`, ignoring case.
+    pub fn from_name(name: &str) -> Option<RootSearch> {
+        RootSearch::ALL
+            .iter()
+            .cloned()
+            .find(|strategy| strategy.name().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchOptions {
+    /// Number of threads searching the position together through the shared table.
+    pub threads: usize,
+    /// Null move pruning; can be switched off to debug the search.
+    pub null_move: bool,
+    /// Verify null move cutoffs at high depth to detect zugzwang.
+    pub verify_null_move: bool,
+    /// Number of best lines to report, each starting with a different move.
+    pub multi_pv: usize,
+    pub root_search: RootSearch,
+    /// Below `MAX_SKILL_LEVEL` the search is cut short and plays a random move among the
+    /// best ones.
+    pub skill_level: u32,
+    /// Keep searching on the opponent's time, on the position after the expected reply.
+    pub ponder: bool,
+    /// Per ply of depth left, how much a quiet move may gain before it is pruned for
+    /// being unable to reach alpha.
+    pub futility_margin: Score,
+    /// Per ply of depth left, how far the static score must be above beta for the node
+    /// to fail high without a search.
+    pub reverse_futility_margin: Score,
+    /// Per ply of depth left, how far the static score must be below alpha for the node
+    /// to drop into the quiescence search.
+    pub razor_margin: Score,
+    /// How much the engine's side prefers playing on to a draw; draws score this much
+    /// below zero for the side to move at the root and above zero for its opponent.
+    pub contempt: Score,
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            threads: 1,
+            null_move: true,
+            verify_null_move: true,
+            multi_pv: 1,
+            root_search: RootSearch::Aspiration,
+            skill_level: MAX_SKILL_LEVEL,
+            ponder: false,
+            futility_margin: 150,
+            reverse_futility_margin: 120,
+            razor_margin: 300,
+            contempt: 0,
+        }
+    }
 }
 
 pub struct InfiniteSearch {
     kill_switch: Arc<AtomicBool>,
+    pondering: Arc<AtomicBool>,
+    cancelled: Arc<AtomicBool>,
     workers: Vec<JoinHandle<()>>,
+    handler: Option<JoinHandle<Option<ISUpdate>>>,
 }
 
 impl InfiniteSearch {
     pub fn start<F>(
-        ttable: &'static TTable,
+        ttable: Arc<TTable>,
         board: Board,
-        max_depth: i32,
+        history: History,
+        limits: SearchLimits,
+        options: SearchOptions,
         mut callback: F,
     ) -> InfiniteSearch
     where
         F: FnMut(ISUpdate) -> () + Send + 'static,
     {
+        let mut limits = limits;
+        limit_search(options.skill_level, &mut limits);
+        ttable.new_search();
         let (sender, receiver) = channel();
+        let mut sender = Some(sender);
         let mut is = InfiniteSearch {
             kill_switch: Arc::new(AtomicBool::new(false)),
+            pondering: Arc::new(AtomicBool::new(limits.ponder)),
+            cancelled: Arc::new(AtomicBool::new(false)),
             workers: Vec::new(),
+            handler: None,
         };
-        let kill_switch = is.kill_switch.clone();
-        let worker =
-            spawn(move || infinite_search(ttable, &board, max_depth, &kill_switch, sender));
-        spawn(move || {
+        let nodes = Arc::new(AtomicUsize::new(0));
+        for thread_id in 0..max(1, options.threads) {
+            let ttable = ttable.clone();
+            let kill_switch = is.kill_switch.clone();
+            let pondering = is.pondering.clone();
+            let nodes = nodes.clone();
+            let history = history.clone();
+            let limits = limits.clone();
+            let options = options.clone();
+            // Only the main thread reports, the helpers just fill the table.
+            let sender = sender.take();
+            let worker = spawn(move || {
+                infinite_search(
+                    &ttable,
+                    &board,
+                    history,
+                    &limits,
+                    &options,
+                    thread_id,
+                    &kill_switch,
+                    &pondering,
+                    &nodes,
+                    sender,
+                )
+            });
+            is.workers.push(worker);
+        }
+        let cancelled = is.cancelled.clone();
+        let handler = spawn(move || {
+            let mut last = None;
             for msg in receiver.iter() {
-                callback(msg);
+                last = Some(msg.clone());
+                if !cancelled.load(Ordering::Relaxed) {
+                    callback(msg);
+                }
             }
-            println!("Handler is dying!");
+            eprintln!("Handler is dying!");
+            last
         });
-        is.workers.push(worker);
+        is.handler = Some(handler);
         is
     }
 
-    pub fn join(mut self) {
-        self.join_internal();
+    /// Asks the search to finish without waiting for its final update.
+    pub fn stop(&self) {
+        self.kill_switch.store(true, Ordering::Relaxed);
     }
 
-    fn join_internal(&mut self) {
+    /// The opponent played the move a pondering search expected: from now on the search
+    /// runs on the engine's own clock.
+    pub fn ponder_hit(&self) {
+        self.pondering.store(false, Ordering::Relaxed);
+    }
+
+    /// Stops the search and returns its final update.
+    pub fn join(mut self) -> Option<ISUpdate> {
+        self.join_internal()
+    }
+
+    /// Stops a search whose result is no longer wanted and returns its final update
+    /// without passing the updates still pending to the callback.
+    pub fn cancel(mut self) -> Option<ISUpdate> {
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.join_internal()
+    }
+
+    fn join_internal(&mut self) -> Option<ISUpdate> {
+        let mut last = None;
         if self.workers.len() > 0 {
             self.kill_switch.store(true, Ordering::Relaxed);
             self.workers.drain(..).for_each(|worker| {
                 worker.join().unwrap();
             });
-            println!("join complete!");
+            // Wait until all pending updates are delivered so that no update of this
+            // search can arrive after whatever the caller does next.
+            if let Some(handler) = self.handler.take() {
+                last = handler.join().unwrap();
+            }
+            eprintln!("join complete!");
         }
+        last
     }
 }
 
@@ -108,149 +368,368 @@ impl Drop for InfiniteSearch {
 pub fn infinite_search(
     ttable: &TTable,
     board: &Board,
-    max_depth: i32,
+    history: History,
+    limits: &SearchLimits,
+    options: &SearchOptions,
+    thread_id: usize,
     kill_switch: &AtomicBool,
-    sender: Sender<ISUpdate>,
+    pondering: &AtomicBool,
+    nodes: &AtomicUsize,
+    sender: Option<Sender<ISUpdate>>,
 ) {
-    let mut ticks = 0;
+    let start = Instant::now();
+    let clock_deadlines = |start| {
+        let mut deadlines = limits.deadlines(start);
+        if sender.is_none() {
+            // Helpers keep going until the main thread decides to stop.
+            deadlines.soft = None;
+        }
+        deadlines
+    };
+    let ponder_pending = Cell::new(pondering.load(Ordering::Relaxed));
+    let deadlines = Cell::new(if ponder_pending.get() {
+        Deadlines {
+            soft: None,
+            hard: None,
+        }
+    } else {
+        clock_deadlines(start)
+    });
+    let max_depth = limits.max_depth();
+    // Odd helpers work one ply ahead of the others to spread the threads over depths.
+    let start_depth = min(3 + (thread_id % 2) as i32, max_depth);
+    let ticks = Cell::new(0usize);
     let mut killed = false;
-    let mut guesses = [0, 0];
-    let entry_op = ttable.fetch(board.get_hash());
-    for depth in min(3, max_depth)..=max_depth {
-        let mut margin = 5;
-        {
-            let mut callback = || {
-                ticks += 1;
-                if ticks % 10000 == 0 && kill_switch.load(Ordering::Relaxed) {
+    let best = {
+        let callback = || {
+            ticks.set(ticks.get() + 1);
+            if ticks.get() % NODE_BATCH == 0 {
+                let total = nodes.fetch_add(NODE_BATCH, Ordering::Relaxed) + NODE_BATCH;
+                if ponder_pending.get() && !pondering.load(Ordering::Relaxed) {
+                    ponder_pending.set(false);
+                    deadlines.set(clock_deadlines(Instant::now()));
+                }
+                if kill_switch.load(Ordering::Relaxed)
+                    || deadlines.get().hard_passed()
+                    || limits
+                        .nodes
+                        .map_or(false, |max_nodes| total as u64 >= max_nodes)
+                {
                     killed = true;
-                    true
-                } else {
-                    false
                 }
+            }
+            killed
+        };
+        let mut searcher = Searcher::new(callback, ttable, options, history, board.side_to_move());
+        searcher.search_moves = MoveGen::new(*board, true)
+            .filter(|cmove| limits.search_moves.contains(cmove))
+            .collect();
+        iterative_deepening(
+            &mut searcher,
+            board,
+            start_depth,
+            max_depth,
+            start,
+            &deadlines,
+            pondering,
+            nodes,
+            sender.as_ref(),
+        )
+    };
+    nodes.fetch_add(ticks.get() % NODE_BATCH, Ordering::Relaxed);
+    let sender = match sender {
+        Some(sender) => sender,
+        None => return,
+    };
+    while !kill_switch.load(Ordering::Relaxed)
+        && (limits.infinite || pondering.load(Ordering::Relaxed))
+    {
+        sleep(Duration::from_millis(1));
+    }
+    kill_switch.store(true, Ordering::Relaxed);
+    // A search stopped before its ponder hit ends still pondering, on a position the
+    // opponent may not have reached.
+    let pondering = pondering.load(Ordering::Relaxed);
+    let update = match best {
+        Some(best) => {
+            let seed = random_seed(board.get_hash());
+            let pick = pick_line(options.skill_level, &best.lines, seed);
+            let mut lines = best.lines.clone();
+            if is_limited(options.skill_level) {
+                lines = lines.drain(pick..).take(1).collect();
+            } else {
+                lines.truncate(options.multi_pv.max(1));
+            }
+            let (line, score, mate) = match lines.first() {
+                Some(line) => (line.line.clone(), line.score, line.mate),
+                None => (best.line.clone(), best.score, best.mate),
             };
-            let gi = (depth as usize) % 2;
-            let mut guess = guesses[gi];
-            if let Some(entry) = entry_op {
-                if entry.depth == depth {
-                    guess = entry.value.as_approximation();
-                }
+            ISUpdate {
+                line,
+                score,
+                mate,
+                lines,
+                stats: SearchStats {
+                    nodes: nodes.load(Ordering::Relaxed) as u64,
+                    ..best.stats
+                },
+                pondering,
+                done: true,
+                ..best
+            }
+        }
+        None => ISUpdate {
+            line: Vec::new(),
+            score: 0,
+            mate: None,
+            lines: Vec::new(),
+            depth: 0,
+            stats: SearchStats {
+                nodes: nodes.load(Ordering::Relaxed) as u64,
+                elapsed: start.elapsed(),
+                ..SearchStats::default()
+            },
+            hashfull: ttable.hashfull(),
+            pondering,
+            done: true,
+        },
+    };
+    let _ = sender.send(update);
+}
+
+/// The window for the next search of the root at one depth, which the results of the
+/// earlier searches narrow down according to the `RootSearch` strategy.
+struct RootWindow {
+    strategy: RootSearch,
+    lower_bound: Score,
+    upper_bound: Score,
+    /// The value of the last search, where MTD(f) places its next null window.
+    guess: Score,
+    /// How far an aspiration window is widened on its next failure.
+    margin: Score,
+}
+
+impl RootWindow {
+    fn new(strategy: RootSearch, depth: i32, guess: Score) -> RootWindow {
+        match strategy {
+            // Shallow values are too unstable for a narrow window to pay off.
+            RootSearch::Aspiration if depth > 4 => RootWindow {
+                strategy,
+                lower_bound: guess - 5,
+                upper_bound: guess + 5,
+                guess,
+                margin: 5,
+            },
+            _ => RootWindow {
+                strategy,
+                lower_bound: MIN_SCORE,
+                upper_bound: MAX_SCORE,
+                guess,
+                margin: 0,
+            },
+        }
+    }
+
+    fn bounds(&self) -> (Score, Score) {
+        match self.strategy {
+            RootSearch::Mtdf => {
+                let beta = max(self.guess, self.lower_bound + 1);
+                (beta - 1, beta)
             }
-            let mut lower_bound = guess - margin;
-            let mut upper_bound = guess + margin;
-            for _retry in 0.. {
-                let (alpha, beta) = if depth <= 4 {
-                    (MIN_SCORE, MAX_SCORE)
+            _ => (self.lower_bound, self.upper_bound),
+        }
+    }
+
+    /// Takes in the `value` of a search with the current bounds and returns whether it
+    /// is the exact value of the root.
+    fn update(&mut self, value: Score) -> bool {
+        let (alpha, beta) = self.bounds();
+        self.guess = value;
+        match self.strategy {
+            RootSearch::Aspiration if value <= alpha => {
+                self.margin *= 2;
+                self.lower_bound = min(value - 1, self.lower_bound - self.margin);
+                false
+            }
+            RootSearch::Aspiration if value >= beta => {
+                self.margin *= 2;
+                self.upper_bound = max(value + 1, self.upper_bound + self.margin);
+                false
+            }
+            RootSearch::Mtdf => {
+                if value < beta {
+                    self.upper_bound = value;
                 } else {
-                    (lower_bound, upper_bound)
-                };
-                match alpha_beta(&mut callback, ttable, board, depth, alpha, beta) {
-                    Some(result) => {
-                        let value = result.best_value;
-                        match alpha_beta_line(&mut || false, ttable, board, depth, result) {
-                            Some(AlphaBetaResult { line, score, .. }) => {
-                                if sender.send(ISUpdate { line, score, depth }).is_err() {
-                                    return;
-                                }
-                            }
-                            None => {
-                                return;
-                            }
-                        }
-                        if value <= alpha {
-                            margin *= 2;
-                            lower_bound = min(value - 1, lower_bound - margin);
-                        } else if value >= beta {
-                            margin *= 2;
-                            upper_bound = max(value + 1, upper_bound + margin);
-                        } else {
-                            guesses[gi] = value;
-                            break;
-                        }
-                    }
-                    None => return,
+                    self.lower_bound = value;
                 }
+                self.lower_bound >= self.upper_bound
             }
-        }
-        if killed {
-            return;
+            _ => true,
         }
     }
 }
 
-fn aspiration_search<F>(
-    callback: &mut F,
-    ttable: &TTable,
+/// Searches with increasing depth until a limit is hit and returns the last update.
+///
+/// In MultiPV mode every depth searches the root once per line, each time without the
+/// root moves of the lines already found.
+fn iterative_deepening<F>(
+    searcher: &mut Searcher<F>,
     board: &Board,
-    entry_op: &Option<TEntry>,
-    moves: &[ChessMove],
-    depth: i32,
-    guess: Score,
-) -> Option<ABResult>
+    start_depth: i32,
+    max_depth: i32,
+    start: Instant,
+    deadlines: &Cell<Deadlines>,
+    pondering: &AtomicBool,
+    nodes: &AtomicUsize,
+    sender: Option<&Sender<ISUpdate>>,
+) -> Option<ISUpdate>
 where
     F: FnMut() -> bool,
 {
-    let mut margin = 5;
-    let mut lower_bound = guess - margin;
-    let mut upper_bound = guess + margin;
-    loop {
-        let (alpha, beta) = if depth <= 2 {
-            (MIN_SCORE, MAX_SCORE)
-        } else {
-            (lower_bound, upper_bound)
-        };
-        match alpha_beta_raw(callback, ttable, board, entry_op, moves, depth, alpha, beta) {
-            Some(result) => {
+    let mut best = None;
+    let num_root_moves = if searcher.search_moves.is_empty() {
+        MoveGen::new(*board, true).len()
+    } else {
+        searcher.search_moves.len()
+    };
+    let multi_pv = if sender.is_some() {
+        let lines = max(
+            searcher.options.multi_pv,
+            candidate_lines(searcher.options.skill_level),
+        );
+        min(lines, num_root_moves).max(1)
+    } else {
+        1
+    };
+    let mut guesses = vec![[0, 0]; multi_pv];
+    // The lines of the last depth searched to the end, with that depth.
+    let mut completed: Option<(i32, Vec<PVLine>)> = None;
+    let entry_op = searcher.ttable.fetch(board.get_hash(), 0);
+    'deepening: for depth in start_depth..=max_depth {
+        let gi = (depth as usize) % 2;
+        let mut lines: Vec<PVLine> = Vec::with_capacity(multi_pv);
+        searcher.excluded_root_moves.clear();
+        for pv_index in 0..multi_pv {
+            let mut guess = guesses[pv_index][gi];
+            if let Some(entry) = entry_op {
+                if pv_index == 0 && entry.depth == depth {
+                    guess = entry.value.as_approximation();
+                }
+            }
+            let mut window = RootWindow::new(searcher.options.root_search, depth, guess);
+            let mut root_move = None;
+            loop {
+                let (alpha, beta) = window.bounds();
+                let result = match searcher.alpha_beta(board, depth, alpha, beta) {
+                    Some(result) => result,
+                    None => break 'deepening,
+                };
                 let value = result.best_value;
-                if value <= alpha {
-                    margin *= 2;
-                    lower_bound = min(value - 1, lower_bound - margin);
-                } else if value >= beta {
-                    margin *= 2;
-                    upper_bound = max(value + 1, upper_bound + margin);
-                } else {
-                    return Some(result);
+                // After a fail low every move is bad and the best move is anyone's guess,
+                // so the line of an earlier search is kept if there is one.
+                let informative = value > alpha || root_move.is_none();
+                if informative {
+                    root_move = result.best_move;
+                }
+                let exact = window.update(value);
+                if let (Some(_), true) = (sender, informative) {
+                    let AlphaBetaResult { line, score } =
+                        match alpha_beta_line(searcher, board, depth, result) {
+                            Some(result) => result,
+                            None => break 'deepening,
+                        };
+                    let mut update_lines = lines.clone();
+                    update_lines.push(PVLine {
+                        line,
+                        score,
+                        mate: mate_distance(score),
+                    });
+                    let update = ISUpdate {
+                        line: update_lines[0].line.clone(),
+                        score: update_lines[0].score,
+                        mate: update_lines[0].mate,
+                        lines: update_lines,
+                        depth,
+                        stats: SearchStats {
+                            // The shared counter only has the batches flushed so far.
+                            nodes: nodes.load(Ordering::Relaxed) as u64
+                                + searcher.stats.nodes % NODE_BATCH as u64,
+                            elapsed: start.elapsed(),
+                            ..searcher.stats
+                        },
+                        hashfull: searcher.ttable.hashfull(),
+                        pondering: pondering.load(Ordering::Relaxed),
+                        done: false,
+                    };
+                    best = Some(update);
+                }
+                if exact {
+                    // Only exact values are reported, the passes before just bound the
+                    // value of the root. The extra candidate lines of a limited skill
+                    // level are only searched to pick from, not shown.
+                    let shown = searcher.options.multi_pv.max(1);
+                    if let (Some(sender), Some(update)) = (sender, best.as_ref()) {
+                        if pv_index < shown {
+                            let mut shown_update = update.clone();
+                            shown_update.lines.truncate(shown);
+                            if sender.send(shown_update).is_err() {
+                                break 'deepening;
+                            }
+                        }
+                    }
+                    guesses[pv_index][gi] = value;
+                    break;
                 }
             }
-            None => return None,
-        };
+            if let Some(ref update) = best {
+                lines = update.lines.clone();
+            }
+            match root_move {
+                Some(root_move) => searcher.excluded_root_moves.push(root_move),
+                None => break,
+            }
+        }
+        if let Some(ref update) = best {
+            completed = Some((depth, update.lines.clone()));
+        }
+        if deadlines.get().soft_passed() {
+            break;
+        }
     }
+    // A limited skill level picks among all its candidate lines, which an unfinished depth
+    // may not have found yet.
+    if is_limited(searcher.options.skill_level) {
+        if let (Some(update), Some((depth, lines))) = (best.as_mut(), completed) {
+            if update.lines.len() < multi_pv {
+                update.depth = depth;
+                update.lines = lines;
+            }
+        }
+    }
+    best
 }
 
-// fn mtdf<'r, 'a: 'r, 'b: 'r, 'c: 'r, F>(callback: &'a mut F, ttable: &'b TTable, board: &'c Board, depth: i32, guess: Score) -> impl Iterator<Item=ABResult> + 'r
-//     where F: FnMut() -> bool {
-//     (0..).scan((MIN_SCORE, MAX_SCORE, guess), move |state, _x| {
-//         let (lower_bound, upper_bound, guess) = state;
-//         if lower_bound >= upper_bound {
-//             return None;
-//         }
-//         let beta = max(*guess, *lower_bound + 1);
-//         let result = alpha_beta(callback, ttable, board, depth, beta - 1, beta)?;
-//         *guess = result.best_value;
-//         if *guess < beta {
-//             *upper_bound = *guess;
-//         } else {
-//             *lower_bound = *guess;
-//         }
-//         Some(result)
-//     })
-// }
-
 // pub fn find_best_move(ttable: &TTable, board: &Board, depth: i32) -> AlphaBetaResult {
 //     let best_move = alpha_beta(&mut || false, ttable, board, depth, MIN_SCORE, MAX_SCORE).unwrap();
 //     let result = alpha_beta_line(&mut || false, ttable, board, depth, best_move).unwrap();
 //     result
 // }
 
+/// Follows the best moves from `result` through null window searches to build the line.
 fn alpha_beta_line<F>(
-    callback: &mut F,
-    ttable: &TTable,
+    searcher: &Searcher<F>,
     board: &Board,
     depth: i32,
     result: ABResult,
-) -> Option<AlphaBetaResult>
-where
-    F: FnMut() -> bool,
-{
+) -> Option<AlphaBetaResult> {
+    let mut line_searcher = Searcher::new(
+        || false,
+        searcher.ttable,
+        searcher.options,
+        searcher.history.clone(),
+        searcher.engine_color,
+    );
     let mut line = Vec::with_capacity(depth as usize);
     let mut sub_board = *board;
     let mut sub_move = result.best_move;
@@ -258,14 +737,18 @@ where
         line.push(cmove);
         let d = line.len() as i32;
         sub_move = if d < depth {
+            line_searcher.history.push(&sub_board, cmove);
+            line_searcher.path.push(Some(cmove));
             sub_board = sub_board.make_move(cmove);
+            if line_searcher.history.is_search_draw(&sub_board) {
+                break;
+            }
             let window = if sub_board.side_to_move() == board.side_to_move() {
                 result.best_value
             } else {
                 -result.best_value
             };
-            let sub_result =
-                alpha_beta(callback, ttable, &sub_board, depth - d, window - 1, window)?;
+            let sub_result = line_searcher.alpha_beta(&sub_board, depth - d, window - 1, window)?;
             sub_result.best_move
         } else {
             None
@@ -277,157 +760,642 @@ where
     })
 }
 
-fn alpha_beta<F>(
-    callback: &mut F,
-    ttable: &TTable,
-    board: &Board,
-    depth: i32,
-    alpha: Score,
-    beta: Score,
-) -> Option<ABResult>
-where
-    F: FnMut() -> bool,
-{
-    let entry = ttable.fetch(board.get_hash());
-    if let Some(result) = entry.and_then(|entry| ABResult::from_tentry(entry, depth)) {
-        Some(result)
-    } else {
-        let mut moves_ar: [ChessMove; 256] = unsafe { mem::uninitialized() };
-        let num_moves = board.enumerate_moves(&mut moves_ar);
-        let moves = &moves_ar[..num_moves];
-        alpha_beta_raw(callback, ttable, board, &entry, moves, depth, alpha, beta)
+/// How many plies less a quiet move is searched, growing with both the remaining
+/// depth and how late the move comes in the ordering.
+fn late_move_reduction(depth: i32, move_index: usize) -> i32 {
+    if depth < LMR_MIN_DEPTH || move_index < LMR_MIN_MOVES {
+        return 0;
     }
+    let reduction = 0.5 + (depth as f64).ln() * (move_index as f64).ln() / 2.25;
+    min(reduction as i32, depth - 2)
 }
 
-fn alpha_beta_raw<F>(
-    callback: &mut F,
-    ttable: &TTable,
-    board: &Board,
-    entry_op: &Option<TEntry>,
-    moves: &[ChessMove],
-    depth: i32,
-    mut alpha: Score,
-    mut beta: Score,
-) -> Option<ABResult>
+fn has_non_pawn_material(board: &Board, color: Color) -> bool {
+    let pawns_and_kings = board.pieces(Piece::Pawn) | board.pieces(Piece::King);
+    board.color_combined(color) & !pawns_and_kings != EMPTY
+}
+
+/// The state of one thread's search that the recursion carries along besides the
+/// position: the stop callback, the shared table, the options and the path so far.
+struct Searcher<'a, F> {
+    callback: F,
+    ttable: &'a TTable,
+    options: &'a SearchOptions,
+    history: History,
+    /// The side to move at the root, which contempt is applied for.
+    engine_color: Color,
+    /// Moves from the root to the current node, `None` for null moves.
+    path: Vec<Option<ChessMove>>,
+    /// Depth of the current iteration, which limits extensions.
+    root_depth: i32,
+    /// Root moves the search is limited to, or empty to search all of them.
+    search_moves: Vec<ChessMove>,
+    /// Root moves left out of the search, those of the lines MultiPV already found.
+    excluded_root_moves: Vec<ChessMove>,
+    ordering: OrderingTables,
+    stats: SearchStats,
+}
+
+impl<'a, F> Searcher<'a, F>
 where
     F: FnMut() -> bool,
 {
-    if callback() {
-        return None;
+    fn new(
+        callback: F,
+        ttable: &'a TTable,
+        options: &'a SearchOptions,
+        history: History,
+        engine_color: Color,
+    ) -> Searcher<'a, F> {
+        Searcher {
+            callback,
+            ttable,
+            options,
+            history,
+            engine_color,
+            path: Vec::with_capacity(MAX_PLY),
+            root_depth: 0,
+            search_moves: Vec::new(),
+            excluded_root_moves: Vec::new(),
+            ordering: OrderingTables::new(),
+            stats: SearchStats::default(),
+        }
     }
-    let alpha_orig = alpha;
-    let score_mul = if board.side_to_move() == Color::White {
-        1
-    } else {
-        -1
-    };
-    if moves.len() == 0 {
-        let value = score_mul * board_score(&board, moves, depth);
-        return Some(ABResult {
-            best_move: None,
-            best_value: value,
-        });
+
+    /// The value of a draw for the side to move on `board`.
+    fn draw_value(&self, board: &Board) -> Score {
+        if board.side_to_move() == self.engine_color {
+            DRAW_SCORE - self.options.contempt
+        } else {
+            DRAW_SCORE + self.options.contempt
+        }
+    }
+
+    /// Looks up `board` in the table for a node `ply` plies from the root.
+    fn probe(&mut self, board: &Board, ply: usize) -> Option<TEntry> {
+        self.stats.tt_probes += 1;
+        let entry = self.ttable.fetch(board.get_hash(), ply as i32);
+        if entry.is_some() {
+            self.stats.tt_hits += 1;
+        }
+        entry
+    }
+
+    fn restricts_root(&self) -> bool {
+        !self.search_moves.is_empty() || !self.excluded_root_moves.is_empty()
+    }
+
+    fn is_root_move(&self, cmove: ChessMove) -> bool {
+        (self.search_moves.is_empty() || self.search_moves.contains(&cmove))
+            && !self.excluded_root_moves.contains(&cmove)
+    }
+
+    fn alpha_beta(
+        &mut self,
+        board: &Board,
+        depth: i32,
+        alpha: Score,
+        beta: Score,
+    ) -> Option<ABResult> {
+        self.root_depth = depth;
+        let ply = self.path.len();
+        let mut entry = self.probe(board, ply);
+        if self.restricts_root() {
+            // The stored result may well be for a move left out now.
+            entry = None;
+        }
+        if let Some(result) = entry.and_then(|entry| ABResult::from_tentry(entry, depth)) {
+            self.stats.tt_cutoffs += 1;
+            Some(result)
+        } else {
+            let mut moves_ar: [ChessMove; 256] = unsafe { mem::uninitialized() };
+            let mut num_moves = board.enumerate_moves(&mut moves_ar);
+            if self.restricts_root() {
+                let mut num_kept = 0;
+                for i in 0..num_moves {
+                    if self.is_root_move(moves_ar[i]) {
+                        moves_ar[num_kept] = moves_ar[i];
+                        num_kept += 1;
+                    }
+                }
+                num_moves = num_kept;
+            }
+            let moves = &moves_ar[..num_moves];
+            self.alpha_beta_raw(board, &entry, moves, depth, alpha, beta, false)
+        }
     }
-    let mut prev_best_move = None;
-    if let Some(entry) = entry_op {
-        prev_best_move = Some(entry.best_move);
-        if entry.depth == depth {
-            match entry.value {
-                ValueInfo::Exact(best_value) => {
+
+    fn alpha_beta_raw(
+        &mut self,
+        board: &Board,
+        entry_op: &Option<TEntry>,
+        moves: &[ChessMove],
+        depth: i32,
+        mut alpha: Score,
+        mut beta: Score,
+        allow_null: bool,
+    ) -> Option<ABResult> {
+        if (self.callback)() {
+            return None;
+        }
+        let ply = self.path.len();
+        self.stats.nodes += 1;
+        self.stats.seldepth = max(self.stats.seldepth, ply as i32);
+        if ply > 0 {
+            // Nothing below this node scores better than mating on the next move or worse
+            // than being mated right here.
+            alpha = max(alpha, mated_in(ply as i32));
+            beta = min(beta, mate_in(ply as i32 + 1));
+            if alpha >= beta {
+                return Some(ABResult {
+                    best_move: None,
+                    best_value: alpha,
+                });
+            }
+        }
+        let alpha_orig = alpha;
+        let pv_node = beta - alpha > 1;
+        let score_mul = if board.side_to_move() == Color::White {
+            1
+        } else {
+            -1
+        };
+        if moves.len() == 0 {
+            let value = if board.checkers().popcnt() == 0 {
+                self.draw_value(board)
+            } else {
+                score_mul * board_score(&board, moves, ply as i32)
+            };
+            return Some(ABResult {
+                best_move: None,
+                best_value: value,
+            });
+        }
+        let mut prev_best_move = None;
+        if let Some(entry) = entry_op {
+            prev_best_move = Some(entry.best_move);
+            if entry.depth == depth {
+                match entry.value {
+                    ValueInfo::Exact(best_value) => {
+                        self.stats.tt_cutoffs += 1;
+                        return Some(ABResult {
+                            best_value,
+                            best_move: Some(entry.best_move),
+                        });
+                    }
+                    ValueInfo::LowerBound(value) => {
+                        alpha = max(alpha, value);
+                    }
+                    ValueInfo::UpperBound(value) => {
+                        beta = min(beta, value);
+                    }
+                }
+                if alpha >= beta {
+                    self.stats.tt_cutoffs += 1;
                     return Some(ABResult {
-                        best_value,
+                        best_value: entry.value.as_approximation(),
                         best_move: Some(entry.best_move),
                     });
                 }
-                ValueInfo::LowerBound(value) => {
-                    alpha = max(alpha, value);
-                }
-                ValueInfo::UpperBound(value) => {
-                    beta = min(beta, value);
-                }
             }
-            if alpha >= beta {
+        }
+
+        let in_check = board.checkers().popcnt() != 0;
+        let static_eval = score_mul * fast_board_score(board);
+        // Nodes off the principal variation are pruned on their static score alone when
+        // it is far enough from the window.
+        let prunable = ply > 0 && !pv_node && !in_check;
+        if prunable
+            && depth <= REVERSE_FUTILITY_MAX_DEPTH
+            && !is_mate_score(beta)
+            && static_eval - self.options.reverse_futility_margin * depth >= beta
+        {
+            return Some(ABResult {
+                best_move: None,
+                best_value: beta,
+            });
+        }
+        if prunable
+            && depth <= RAZOR_MAX_DEPTH
+            && !is_mate_score(alpha)
+            && static_eval + self.options.razor_margin * depth <= alpha
+        {
+            // Only tactics could save a position this bad, so check them alone first.
+            let value = self.quiescence(board, ply as i32, alpha, alpha + 1)?;
+            if value <= alpha {
                 return Some(ABResult {
-                    best_value: entry.value.as_approximation(),
-                    best_move: Some(entry.best_move),
+                    best_move: None,
+                    best_value: value,
                 });
             }
         }
-    }
+        let futility_value = static_eval + self.options.futility_margin * depth;
+        let futile = prunable
+            && depth <= FUTILITY_MAX_DEPTH
+            && !is_mate_score(alpha)
+            && futility_value <= alpha;
 
-    let mut moves_data: [(Board, Option<TEntry>); 256] = unsafe { mem::uninitialized() };
-    let mut ordered_moves: [(u8, Score); 256] = unsafe { mem::uninitialized() };
-    let mut best_value = MIN_SCORE;
-    let mut best_move = moves[0];
-    for (i, &cmove) in moves.iter().enumerate() {
-        let move_board = board.make_move(cmove);
-        let move_entry = ttable.fetch(move_board.get_hash());
-        let mut fast_score = move_entry
-            .map(|x| x.value.as_approximation())
-            .unwrap_or_else(|| score_mul * fast_board_score(&move_board, depth as i32));
-        if Some(cmove) == prev_best_move {
-            fast_score += 1000;
-        }
-        moves_data[i] = (move_board, move_entry);
-        ordered_moves[i] = (i as u8, fast_score);
-    }
-    ordered_moves[..moves.len()].sort_unstable_by(|(_, a), (_, b)| b.cmp(&a));
-
-    for (ind, _) in ordered_moves[..moves.len()].iter() {
-        let i = *ind as usize;
-        let (move_board, move_entry) = &moves_data[i];
-        let cmove = moves[i];
-        let value;
-        if let Some(sub_result) =
-            move_entry.and_then(|move_entry| ABResult::from_tentry(move_entry, depth - 1))
+        if allow_null
+            && self.options.null_move
+            && depth >= NULL_MOVE_MIN_DEPTH
+            && !is_mate_score(beta)
+            && !in_check
+            && has_non_pawn_material(board, board.side_to_move())
+            && static_eval >= beta
+            && self.null_move_cutoff(board, moves, depth, beta)?
         {
-            value = -sub_result.best_value;
+            return Some(ABResult {
+                best_move: None,
+                best_value: beta,
+            });
+        }
+
+        let singular_move = match *entry_op {
+            Some(entry)
+                if depth >= SINGULAR_MIN_DEPTH
+                    && entry.depth >= depth - 3
+                    && moves.contains(&entry.best_move)
+                    && self.can_extend() =>
+            {
+                match entry.value {
+                    ValueInfo::LowerBound(value) | ValueInfo::Exact(value)
+                        if !is_mate_score(value) =>
+                    {
+                        let singular_beta = value - SINGULAR_MARGIN * depth;
+                        if self.is_singular(
+                            board,
+                            moves,
+                            entry.best_move,
+                            depth / 2,
+                            singular_beta,
+                        )? {
+                            Some(entry.best_move)
+                        } else {
+                            None
+                        }
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        // Without a TT move the ordering is a guess. On the principal variation a shallower
+        // search provides a move to try first; elsewhere the node is not likely to matter
+        // much and is searched at a reduced depth.
+        let mut depth = depth;
+        if prev_best_move.is_none() && !pv_node && depth >= IIR_MIN_DEPTH {
+            depth -= 1;
+        }
+        if prev_best_move.is_none() && pv_node && depth >= IID_MIN_DEPTH {
+            let result = self.alpha_beta_raw(
+                board,
+                &None,
+                moves,
+                depth - IID_REDUCTION,
+                alpha,
+                beta,
+                false,
+            )?;
+            prev_best_move = result.best_move;
+        }
+
+        let prev_move = self.path.last().cloned().and_then(|cmove| cmove);
+        let mut picker = MovePicker::new(
+            board,
+            moves,
+            prev_best_move,
+            self.ordering.killers(ply),
+            self.ordering.countermove(prev_move),
+        );
+        let mut quiets_tried: [ChessMove; 256] = [ChessMove::default(); 256];
+        let mut num_quiets_tried = 0;
+        let mut best_value = MIN_SCORE;
+        let mut best_move = moves[0];
+        let mut move_index = 0;
+        while let Some(cmove) = picker.next(&self.ordering) {
+            let move_board = board.make_move(cmove);
+            let move_entry = self.probe(&move_board, ply + 1);
+            let quiet = !is_tactical(board, cmove);
+            let gives_check = move_board.checkers().popcnt() != 0;
+            if futile && move_index > 0 && quiet && !gives_check {
+                best_value = max(best_value, futility_value);
+                continue;
+            }
+            let extension = if !self.can_extend() {
+                0
+            } else if (gives_check && see(board, cmove) >= 0) || Some(cmove) == singular_move {
+                1
+            } else {
+                0
+            };
+            let child_depth = depth - 1 + extension;
+            let value;
+            self.history.push(board, cmove);
+            self.path.push(Some(cmove));
+            if self.history.is_search_draw(&move_board) {
+                value = self.draw_value(board);
+            } else if let Some(sub_result) =
+                move_entry.and_then(|move_entry| ABResult::from_tentry(move_entry, child_depth))
+            {
+                self.stats.tt_cutoffs += 1;
+                value = -sub_result.best_value;
+            } else if move_index == 0 {
+                value = self.search_child(&move_board, &move_entry, child_depth, alpha, beta)?;
+            } else {
+                // Later moves only have to prove that they are no better than the best
+                // move so far, which a null window search around alpha does cheaply.
+                let reducible = quiet && !in_check && !gives_check;
+                let reduction = if reducible {
+                    late_move_reduction(depth, move_index)
+                } else {
+                    0
+                };
+                let mut score = self.search_child(
+                    &move_board,
+                    &move_entry,
+                    child_depth - reduction,
+                    alpha,
+                    alpha + 1,
+                )?;
+                if score > alpha && reduction > 0 {
+                    score =
+                        self.search_child(&move_board, &move_entry, child_depth, alpha, alpha + 1)?;
+                }
+                if score > alpha && score < beta {
+                    score =
+                        self.search_child(&move_board, &move_entry, child_depth, alpha, beta)?;
+                }
+                value = score;
+            }
+            self.path.pop();
+            self.history.pop();
+
+            if value > best_value {
+                best_value = value;
+                best_move = cmove;
+                alpha = max(alpha, value);
+                if alpha >= beta {
+                    self.stats.fail_highs += 1;
+                    if move_index == 0 {
+                        self.stats.first_move_fail_highs += 1;
+                    }
+                    if quiet {
+                        self.ordering.record_cutoff(
+                            ply,
+                            prev_move,
+                            cmove,
+                            depth,
+                            &quiets_tried[..num_quiets_tried],
+                        );
+                    }
+                    break;
+                }
+            }
+            if quiet {
+                quiets_tried[num_quiets_tried] = cmove;
+                num_quiets_tried += 1;
+            }
+            move_index += 1;
+        }
+        let value = if best_value <= alpha_orig {
+            ValueInfo::UpperBound(best_value)
+        } else if best_value >= beta {
+            ValueInfo::LowerBound(best_value)
         } else {
+            ValueInfo::Exact(best_value)
+        };
+        let entry = TEntry {
+            hash: board.get_hash(),
+            depth,
+            age: self.ttable.generation(),
+            value,
+            best_move,
+        };
+        if ply > 0 || !self.restricts_root() {
+            self.ttable.put(entry, ply as i32);
+        }
+        Some(ABResult {
+            best_move: Some(best_move),
+            best_value,
+        })
+    }
+
+    /// Extensions stop at twice the depth of the iteration, which bounds how far forcing
+    /// lines such as series of checks can stretch the search.
+    fn can_extend(&self) -> bool {
+        self.path.len() < 2 * self.root_depth as usize
+    }
+
+    /// Whether every move but `tt_move` fails low against `beta` in a search at `depth`,
+    /// which makes `tt_move` the only good move and worth searching deeper.
+    fn is_singular(
+        &mut self,
+        board: &Board,
+        moves: &[ChessMove],
+        tt_move: ChessMove,
+        depth: i32,
+        beta: Score,
+    ) -> Option<bool> {
+        let ply = self.path.len();
+        let prev_move = self.path.last().cloned().and_then(|cmove| cmove);
+        let mut picker = MovePicker::new(
+            board,
+            moves,
+            None,
+            self.ordering.killers(ply),
+            self.ordering.countermove(prev_move),
+        );
+        while let Some(cmove) = picker.next(&self.ordering) {
+            if cmove == tt_move {
+                continue;
+            }
+            let move_board = board.make_move(cmove);
+            self.history.push(board, cmove);
+            self.path.push(Some(cmove));
+            let value = if self.history.is_search_draw(&move_board) {
+                self.draw_value(board)
+            } else {
+                let move_entry = self.probe(&move_board, ply + 1);
+                self.search_child(&move_board, &move_entry, depth - 1, beta - 1, beta)?
+            };
+            self.path.pop();
+            self.history.pop();
+            if value >= beta {
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
+
+    /// Searches the position after a move at `depth` and returns its value for the side
+    /// that made the move; at depth zero and below only captures are searched.
+    fn search_child(
+        &mut self,
+        move_board: &Board,
+        move_entry: &Option<TEntry>,
+        depth: i32,
+        alpha: Score,
+        beta: Score,
+    ) -> Option<Score> {
+        if depth > 0 {
             let mut sub_moves_ar: [ChessMove; 256] = unsafe { mem::uninitialized() };
             let sub_num_moves = move_board.enumerate_moves(&mut sub_moves_ar);
             let sub_moves = &sub_moves_ar[..sub_num_moves];
-            if depth > 1 {
-                let sub_result = alpha_beta_raw(
-                    callback,
-                    ttable,
-                    move_board,
-                    move_entry,
-                    sub_moves,
-                    depth - 1,
-                    -beta,
-                    -alpha,
-                )?;
-                value = -sub_result.best_value;
+            // A forced reply costs nothing to search one ply deeper.
+            let extension = if sub_num_moves == 1 && self.can_extend() {
+                1
             } else {
-                value = score_mul * board_score(&move_board, sub_moves, depth);
+                0
+            };
+            let sub_result = self.alpha_beta_raw(
+                move_board,
+                move_entry,
+                sub_moves,
+                depth + extension,
+                -beta,
+                -alpha,
+                true,
+            )?;
+            Some(-sub_result.best_value)
+        } else {
+            let ply = self.path.len() as i32;
+            Some(-self.quiescence(move_board, ply, -beta, -alpha)?)
+        }
+    }
+
+    /// Lets the opponent move twice; if a reduced search still fails high the node is
+    /// assumed to fail high as well. The verification search guards against zugzwang,
+    /// where passing would be the best move.
+    fn null_move_cutoff(
+        &mut self,
+        board: &Board,
+        moves: &[ChessMove],
+        depth: i32,
+        beta: Score,
+    ) -> Option<bool> {
+        let null_board = match board.null_move() {
+            Some(null_board) => null_board,
+            None => return Some(false),
+        };
+        let reduction = NULL_MOVE_REDUCTION + depth / 6;
+        let null_depth = depth - 1 - reduction;
+        self.history.push_null(&null_board);
+        self.path.push(None);
+        let value = if null_depth > 0 {
+            let ply = self.path.len();
+            let entry = self.probe(&null_board, ply);
+            let mut null_moves_ar: [ChessMove; 256] = [ChessMove::default(); 256];
+            let num_null_moves = null_board.enumerate_moves(&mut null_moves_ar);
+            let null_moves = &null_moves_ar[..num_null_moves];
+            let result = self.alpha_beta_raw(
+                &null_board,
+                &entry,
+                null_moves,
+                null_depth,
+                -beta,
+                -beta + 1,
+                false,
+            )?;
+            -result.best_value
+        } else {
+            -self.quiescence(&null_board, self.path.len() as i32, -beta, -beta + 1)?
+        };
+        self.path.pop();
+        self.history.pop();
+        if value < beta {
+            return Some(false);
+        }
+        if self.options.verify_null_move && depth >= NULL_MOVE_VERIFY_DEPTH {
+            let ply = self.path.len();
+            let entry = self.probe(board, ply);
+            let result = self.alpha_beta_raw(
+                board,
+                &entry,
+                moves,
+                depth - reduction,
+                beta - 1,
+                beta,
+                false,
+            )?;
+            return Some(result.best_value >= beta);
+        }
+        Some(true)
+    }
+
+    /// Searches captures and promotions (or all evasions when in check) until the position
+    /// is quiet, so that leaf scores are not taken in the middle of an exchange.
+    fn quiescence(
+        &mut self,
+        board: &Board,
+        ply: i32,
+        mut alpha: Score,
+        beta: Score,
+    ) -> Option<Score> {
+        if (self.callback)() {
+            return None;
+        }
+        self.stats.nodes += 1;
+        self.stats.qnodes += 1;
+        self.stats.seldepth = max(self.stats.seldepth, ply);
+        let score_mul = if board.side_to_move() == Color::White {
+            1
+        } else {
+            -1
+        };
+        let mut moves_ar: [ChessMove; 256] = unsafe { mem::uninitialized() };
+        let num_moves = board.enumerate_moves(&mut moves_ar);
+        let moves = &moves_ar[..num_moves];
+        let in_check = board.checkers().popcnt() > 0;
+        if moves.len() == 0 && !in_check {
+            return Some(self.draw_value(board));
+        }
+        let mut stand_pat = MIN_SCORE;
+        if moves.len() == 0 || !in_check {
+            stand_pat = score_mul * board_score(board, moves, ply);
+            if moves.len() == 0 || stand_pat >= beta {
+                return Some(stand_pat);
             }
+            alpha = max(alpha, stand_pat);
         }
 
-        if value > best_value {
-            best_value = value;
-            best_move = cmove;
-            alpha = max(alpha, value);
-            if alpha >= beta {
-                break;
+        let mut ordered_moves: [(u8, Score); 256] = [(0, 0); 256];
+        let mut num_ordered = 0;
+        for (i, &cmove) in moves.iter().enumerate() {
+            if !in_check {
+                match cmove.get_promotion() {
+                    Some(Piece::Queen) => (),
+                    Some(_) => continue,
+                    None if !is_tactical(board, cmove) => continue,
+                    None => (),
+                }
+                if cmove.get_promotion().is_none()
+                    && stand_pat + tactical_gain(board, cmove) + DELTA_MARGIN <= alpha
+                {
+                    continue;
+                }
+                // Captures that lose material in the exchange are not worth searching.
+                if see(board, cmove) < 0 {
+                    continue;
+                }
             }
+            ordered_moves[num_ordered] = (i as u8, mvv_lva_score(board, cmove));
+            num_ordered += 1;
         }
+        ordered_moves[..num_ordered].sort_unstable_by(|(_, a), (_, b)| b.cmp(&a));
+
+        let mut best_value = stand_pat;
+        for (ind, _) in ordered_moves[..num_ordered].iter() {
+            let move_board = board.make_move(moves[*ind as usize]);
+            let value = -self.quiescence(&move_board, ply + 1, -beta, -alpha)?;
+            if value > best_value {
+                best_value = value;
+                alpha = max(alpha, value);
+                if alpha >= beta {
+                    break;
+                }
+            }
+        }
+        Some(best_value)
     }
-    let value = if best_value <= alpha_orig {
-        ValueInfo::UpperBound(best_value)
-    } else if best_value >= beta {
-        ValueInfo::LowerBound(best_value)
-    } else {
-        ValueInfo::Exact(best_value)
-    };
-    let entry = TEntry {
-        hash: board.get_hash(),
-        depth,
-        value,
-        best_move,
-    };
-    ttable.put(entry);
-    Some(ABResult {
-        best_move: Some(best_move),
-        best_value,
-    })
 }
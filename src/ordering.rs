@@ -0,0 +1,249 @@
+use std::cmp::min;
+
+use chess::*;
+
+use eval::*;
+
+/// Number of plies from the root for which killer moves are kept.
+pub const MAX_PLY: usize = 128;
+
+/// History scores stay within `-HISTORY_MAX..=HISTORY_MAX`.
+const HISTORY_MAX: i32 = 1 << 14;
+const HISTORY_MAX_BONUS: i32 = 400;
+
+fn from_to_index(cmove: ChessMove) -> usize {
+    cmove.get_source().to_index() * 64 + cmove.get_dest().to_index()
+}
+
+/// Quiet moves that caused beta cutoffs earlier in the search, used to order the moves of
+/// later nodes. Each search thread owns its own tables.
+pub struct OrderingTables {
+    /// Two most recent cutoff moves per ply.
+    killers: Vec<[Option<ChessMove>; 2]>,
+    /// Butterfly history indexed by source and destination square.
+    butterfly: Vec<i32>,
+    /// The quiet move that last refuted a move, indexed by that move's squares.
+    countermoves: Vec<Option<ChessMove>>,
+}
+
+impl OrderingTables {
+    pub fn new() -> OrderingTables {
+        OrderingTables {
+            killers: vec![[None; 2]; MAX_PLY],
+            butterfly: vec![0; 64 * 64],
+            countermoves: vec![None; 64 * 64],
+        }
+    }
+
+    pub fn killers(&self, ply: usize) -> [Option<ChessMove>; 2] {
+        self.killers.get(ply).cloned().unwrap_or([None; 2])
+    }
+
+    pub fn countermove(&self, prev_move: Option<ChessMove>) -> Option<ChessMove> {
+        prev_move.and_then(|prev_move| self.countermoves[from_to_index(prev_move)])
+    }
+
+    pub fn history_score(&self, cmove: ChessMove) -> i32 {
+        self.butterfly[from_to_index(cmove)]
+    }
+
+    /// Records the quiet `cmove` that failed high at `ply` after `prev_move`, and
+    /// penalises the quiet moves in `tried` that were searched before it without success.
+    pub fn record_cutoff(
+        &mut self,
+        ply: usize,
+        prev_move: Option<ChessMove>,
+        cmove: ChessMove,
+        depth: i32,
+        tried: &[ChessMove],
+    ) {
+        if let Some(killers) = self.killers.get_mut(ply) {
+            if killers[0] != Some(cmove) {
+                killers[1] = killers[0];
+                killers[0] = Some(cmove);
+            }
+        }
+        if let Some(prev_move) = prev_move {
+            self.countermoves[from_to_index(prev_move)] = Some(cmove);
+        }
+        let bonus = min(depth * depth, HISTORY_MAX_BONUS);
+        self.update_history(cmove, bonus);
+        for &quiet in tried {
+            self.update_history(quiet, -bonus);
+        }
+    }
+
+    fn update_history(&mut self, cmove: ChessMove, bonus: i32) {
+        // Scale the update down as the entry approaches its bound, so old cutoffs fade.
+        let entry = &mut self.butterfly[from_to_index(cmove)];
+        *entry += bonus - *entry * bonus.abs() / HISTORY_MAX;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stage {
+    TTMove,
+    GoodCaptures,
+    Killers,
+    Quiets,
+    BadCaptures,
+    Done,
+}
+
+/// Hands out the moves of a node one at a time, best first, scoring each group of moves
+/// only once the earlier groups are exhausted so a cutoff skips the remaining work.
+///
+/// Order: the TT move, captures and queen promotions that do not lose material in the
+/// exchange by MVV-LVA, killers and the countermove, quiet moves by history score, and
+/// finally the captures that lose material.
+pub struct MovePicker<'a> {
+    board: &'a Board,
+    moves: &'a [ChessMove],
+    tt_move: Option<ChessMove>,
+    /// Killer moves and the countermove, with duplicates and illegal moves removed.
+    refutations: [Option<ChessMove>; 3],
+    stage: Stage,
+    /// Moves of the current stage with their ordering key; `scored[next..end]` are unused.
+    scored: [(ChessMove, i32); 256],
+    next: usize,
+    end: usize,
+    bad_captures: [(ChessMove, i32); 256],
+    num_bad_captures: usize,
+}
+
+impl<'a> MovePicker<'a> {
+    pub fn new(
+        board: &'a Board,
+        moves: &'a [ChessMove],
+        tt_move: Option<ChessMove>,
+        killers: [Option<ChessMove>; 2],
+        countermove: Option<ChessMove>,
+    ) -> MovePicker<'a> {
+        let tt_move = tt_move.filter(|cmove| moves.contains(cmove));
+        let mut refutations = [killers[0], killers[1], countermove];
+        for i in 0..refutations.len() {
+            let valid = refutations[i].map_or(false, |cmove| {
+                Some(cmove) != tt_move
+                    && !refutations[..i].contains(&Some(cmove))
+                    && !is_tactical(board, cmove)
+                    && moves.contains(&cmove)
+            });
+            if !valid {
+                refutations[i] = None;
+            }
+        }
+        MovePicker {
+            board,
+            moves,
+            tt_move,
+            refutations,
+            stage: Stage::TTMove,
+            scored: [(ChessMove::default(), 0); 256],
+            next: 0,
+            end: 0,
+            bad_captures: [(ChessMove::default(), 0); 256],
+            num_bad_captures: 0,
+        }
+    }
+
+    pub fn next(&mut self, tables: &OrderingTables) -> Option<ChessMove> {
+        loop {
+            match self.stage {
+                Stage::TTMove => {
+                    self.stage = Stage::GoodCaptures;
+                    self.score_captures();
+                    if self.tt_move.is_some() {
+                        return self.tt_move;
+                    }
+                }
+                Stage::GoodCaptures => match self.pick_best() {
+                    Some(cmove) => return Some(cmove),
+                    None => {
+                        self.stage = Stage::Killers;
+                        self.next = 0;
+                    }
+                },
+                Stage::Killers => {
+                    while self.next < self.refutations.len() {
+                        self.next += 1;
+                        if self.refutations[self.next - 1].is_some() {
+                            return self.refutations[self.next - 1];
+                        }
+                    }
+                    self.stage = Stage::Quiets;
+                    self.score_quiets(tables);
+                }
+                Stage::Quiets => match self.pick_best() {
+                    Some(cmove) => return Some(cmove),
+                    None => {
+                        self.stage = Stage::BadCaptures;
+                        let num_bad_captures = self.num_bad_captures;
+                        self.scored[..num_bad_captures]
+                            .copy_from_slice(&self.bad_captures[..num_bad_captures]);
+                        self.next = 0;
+                        self.end = num_bad_captures;
+                    }
+                },
+                Stage::BadCaptures => match self.pick_best() {
+                    Some(cmove) => return Some(cmove),
+                    None => self.stage = Stage::Done,
+                },
+                Stage::Done => return None,
+            }
+        }
+    }
+
+    fn is_refutation(&self, cmove: ChessMove) -> bool {
+        Some(cmove) == self.tt_move || self.refutations.contains(&Some(cmove))
+    }
+
+    fn score_captures(&mut self) {
+        self.next = 0;
+        self.end = 0;
+        for &cmove in self.moves {
+            if !is_tactical(self.board, cmove) || Some(cmove) == self.tt_move {
+                continue;
+            }
+            let score = mvv_lva_score(self.board, cmove);
+            let good = match cmove.get_promotion() {
+                None | Some(Piece::Queen) => see(self.board, cmove) >= 0,
+                Some(_) => false,
+            };
+            if good {
+                self.scored[self.end] = (cmove, score);
+                self.end += 1;
+            } else {
+                self.bad_captures[self.num_bad_captures] = (cmove, score);
+                self.num_bad_captures += 1;
+            }
+        }
+    }
+
+    fn score_quiets(&mut self, tables: &OrderingTables) {
+        self.next = 0;
+        self.end = 0;
+        for &cmove in self.moves {
+            if is_tactical(self.board, cmove) || self.is_refutation(cmove) {
+                continue;
+            }
+            self.scored[self.end] = (cmove, tables.history_score(cmove));
+            self.end += 1;
+        }
+    }
+
+    /// Selection sort step: moves are picked lazily since most nodes only need a few.
+    fn pick_best(&mut self) -> Option<ChessMove> {
+        if self.next >= self.end {
+            return None;
+        }
+        let mut best = self.next;
+        for i in self.next + 1..self.end {
+            if self.scored[i].1 > self.scored[best].1 {
+                best = i;
+            }
+        }
+        self.scored.swap(self.next, best);
+        self.next += 1;
+        Some(self.scored[self.next - 1].0)
+    }
+}
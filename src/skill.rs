@@ -0,0 +1,89 @@
+use std::cmp::{max, min};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use limits::*;
+use minmax::PVLine;
+
+/// Skill levels run from 0, the weakest, to `MAX_SKILL_LEVEL`, which is full strength.
+pub const MAX_SKILL_LEVEL: u32 = 20;
+
+/// The range of Elo ratings that is mapped linearly onto the skill levels.
+pub const MIN_ELO: u32 = 800;
+pub const MAX_ELO: u32 = 2400;
+
+/// Number of best root moves a limited skill level chooses from.
+const SKILL_CANDIDATES: usize = 4;
+
+pub fn is_limited(level: u32) -> bool {
+    level < MAX_SKILL_LEVEL
+}
+
+/// The skill level that plays at about `elo`.
+pub fn skill_level_for_elo(elo: u32) -> u32 {
+    let elo = min(max(elo, MIN_ELO), MAX_ELO);
+    (elo - MIN_ELO) * MAX_SKILL_LEVEL / (MAX_ELO - MIN_ELO)
+}
+
+/// Caps the depth and node count of a search at skill `level`, on top of the limits the
+/// search already has.
+pub fn limit_search(level: u32, limits: &mut SearchLimits) {
+    if !is_limited(level) {
+        return;
+    }
+    let depth = 1 + level as i32 / 2;
+    let nodes = 1000 * (level as u64 + 1).pow(2);
+    limits.depth = Some(limits.depth.map_or(depth, |d| min(d, depth)));
+    limits.nodes = Some(limits.nodes.map_or(nodes, |n| min(n, nodes)));
+}
+
+/// Number of lines the search has to find for `pick_line` to choose from.
+pub fn candidate_lines(level: u32) -> usize {
+    if is_limited(level) {
+        SKILL_CANDIDATES
+    } else {
+        1
+    }
+}
+
+/// Picks the index of the line to play among `lines`. Lines are less likely the more they
+/// lose compared to the best one, and more so at higher levels.
+pub fn pick_line(level: u32, lines: &[PVLine], seed: u64) -> usize {
+    if !is_limited(level) || lines.len() <= 1 {
+        return 0;
+    }
+    // A line that loses this many centipawns is e times less likely than the best.
+    let temperature = 10.0 + 15.0 * (MAX_SKILL_LEVEL - level) as f64;
+    // The lines of a shallow search are not always in order of their scores.
+    let best = lines.iter().map(|line| line.score).max().unwrap_or(0);
+    let weights: Vec<f64> = lines
+        .iter()
+        .map(|line| (-(best - line.score) as f64 / temperature).exp())
+        .collect();
+    let total: f64 = weights.iter().sum();
+    let mut target = random_fraction(seed) * total;
+    for (i, &weight) in weights.iter().enumerate() {
+        if target < weight {
+            return i;
+        }
+        target -= weight;
+    }
+    lines.len() - 1
+}
+
+/// A seed that differs between moves, mixed with `hash` to differ between positions.
+pub fn random_seed(hash: u64) -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.subsec_nanos() as u64)
+        .unwrap_or(0);
+    nanos ^ hash
+}
+
+/// A number in `[0, 1)` derived from `seed` by a splitmix64 step.
+fn random_fraction(seed: u64) -> f64 {
+    let mut x = seed.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^= x >> 31;
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -1,10 +1,10 @@
 use eval::*;
+use std::cmp::{max, min};
 use std::mem;
 use std::ops;
 
-use atomic_option::AtomicOption;
-
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use chess::*;
 
@@ -27,6 +27,41 @@ impl ValueInfo {
     }
 }
 
+impl ValueInfo {
+    fn map<F>(self, f: F) -> ValueInfo
+    where
+        F: Fn(Score) -> Score,
+    {
+        match self {
+            Exact(s) => Exact(f(s)),
+            LowerBound(s) => LowerBound(f(s)),
+            UpperBound(s) => UpperBound(f(s)),
+        }
+    }
+}
+
+/// Mate scores count plies from the root, but the table stores them counted from the
+/// entry's own position so they stay correct when it is reached at another ply.
+fn score_to_table(score: Score, ply: i32) -> Score {
+    if !is_mate_score(score) {
+        score
+    } else if score > 0 {
+        score + ply
+    } else {
+        score - ply
+    }
+}
+
+fn score_from_table(score: Score, ply: i32) -> Score {
+    if !is_mate_score(score) {
+        score
+    } else if score > 0 {
+        score - ply
+    } else {
+        score + ply
+    }
+}
+
 impl ops::Neg for ValueInfo {
     type Output = ValueInfo;
 
@@ -43,59 +78,256 @@ impl ops::Neg for ValueInfo {
 pub struct TEntry {
     pub hash: u64,
     pub depth: i32,
-    // pub age: i32,
+    /// The search generation that stored the entry.
+    pub age: u8,
     pub value: ValueInfo,
     pub best_move: ChessMove,
 }
 
+pub const MB: usize = 1024 * 1024;
+pub const DEFAULT_HASH_MB: usize = 100;
+/// The largest table the front-ends accept.
+pub const MAX_HASH_MB: usize = 65536;
+
+/// Entries per bucket; four 16 byte entries fill one cache line.
+const BUCKET_SIZE: usize = 4;
+
+const BOUND_EXACT: u64 = 1;
+const BOUND_LOWER: u64 = 2;
+const BOUND_UPPER: u64 = 3;
+
+/// Generations wrap around after this many searches.
+const GENERATIONS: u8 = 64;
+/// Number of entries sampled by `hashfull`.
+const HASHFULL_SAMPLE: usize = 1000;
+/// How many plies of depth one generation of age is worth when choosing a victim.
+const AGE_WEIGHT: i32 = 8;
+
+/// An entry stored as the packed data word and the hash xor-ed with it, so that a
+/// reader can detect an entry torn by a concurrent writer.
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+#[repr(align(64))]
+struct Bucket {
+    slots: [Slot; BUCKET_SIZE],
+}
+
+impl Bucket {
+    fn new() -> Bucket {
+        Bucket {
+            slots: [Slot::new(), Slot::new(), Slot::new(), Slot::new()],
+        }
+    }
+}
+
+impl Slot {
+    fn new() -> Slot {
+        Slot {
+            key: AtomicU64::new(0),
+            data: AtomicU64::new(0),
+        }
+    }
+
+    /// The data word if the slot holds an entry for `hash`.
+    fn load(&self, hash: u64) -> Option<u64> {
+        let data = self.data.load(Ordering::Relaxed);
+        let key = self.key.load(Ordering::Relaxed);
+        if data != 0 && key ^ data == hash {
+            Some(data)
+        } else {
+            None
+        }
+    }
+
+    fn store(&self, hash: u64, data: u64) {
+        self.data.store(data, Ordering::Relaxed);
+        self.key.store(hash ^ data, Ordering::Relaxed);
+    }
+}
+
+// Data word layout: value (32 bits), move (16), depth (8), bound (2), age (6). A bound
+// of zero marks an empty slot.
+fn pack(entry: &TEntry) -> u64 {
+    let (bound, value) = match entry.value {
+        Exact(s) => (BOUND_EXACT, s),
+        LowerBound(s) => (BOUND_LOWER, s),
+        UpperBound(s) => (BOUND_UPPER, s),
+    };
+    let cmove = entry.best_move;
+    let promotion = cmove
+        .get_promotion()
+        .map_or(0, |piece| piece.to_index() as u64 + 1);
+    let packed_move = cmove.get_source().to_index() as u64
+        | (cmove.get_dest().to_index() as u64) << 6
+        | promotion << 12;
+    let depth = max(0, min(entry.depth, 255)) as u64;
+    let age = (entry.age % GENERATIONS) as u64;
+    (value as u32 as u64) | packed_move << 32 | depth << 48 | bound << 56 | age << 58
+}
+
+fn unpack(hash: u64, data: u64) -> TEntry {
+    let value = data as u32 as i32;
+    let value = match (data >> 56) & 3 {
+        BOUND_EXACT => Exact(value),
+        BOUND_LOWER => LowerBound(value),
+        _ => UpperBound(value),
+    };
+    let packed_move = (data >> 32) & 0xffff;
+    let promotion = match (packed_move >> 12) & 7 {
+        0 => None,
+        index => Some(ALL_PIECES[index as usize - 1]),
+    };
+    let best_move = ChessMove::new(
+        ALL_SQUARES[(packed_move & 63) as usize],
+        ALL_SQUARES[((packed_move >> 6) & 63) as usize],
+        promotion,
+    );
+    TEntry {
+        hash,
+        depth: depth_of(data),
+        age: age_of(data),
+        value,
+        best_move,
+    }
+}
+
+fn depth_of(data: u64) -> i32 {
+    ((data >> 48) & 0xff) as i32
+}
+
+fn age_of(data: u64) -> u8 {
+    (data >> 58) as u8
+}
+
+/// A lock-free transposition table shared by all search threads.
 pub struct TTable {
-    table: Vec<AtomicOption<TEntry>>,
+    table: Vec<Bucket>,
+    generation: AtomicUsize,
 }
 
 impl TTable {
     pub fn new(capacity: usize) -> TTable {
-        let entry_size = mem::size_of::<TEntry>();
-        let num = capacity / entry_size;
+        let num = max(1, capacity / mem::size_of::<Bucket>());
         let mut table = Vec::with_capacity(num);
         for _ in 0..num {
-            table.push(AtomicOption::empty());
+            table.push(Bucket::new());
+        }
+        TTable {
+            table,
+            generation: AtomicUsize::new(0),
         }
-        TTable { table }
     }
 
-    pub fn put(&self, entry: TEntry) {
-        let index = self.hash_index(entry.hash);
-        let bucket = &self.table[index];
-        let boxed_entry = match bucket.take(Ordering::Relaxed) {
-            None => Box::new(entry),
-            Some(mut cur_entry) => {
-                if cur_entry.hash != entry.hash || entry.depth >= cur_entry.depth {
-                    *cur_entry = entry;
-                }
-                cur_entry
+    /// The size of the table in bytes.
+    pub fn capacity(&self) -> usize {
+        self.table.len() * mem::size_of::<Bucket>()
+    }
+
+    /// Removes all entries.
+    pub fn clear(&self) {
+        for bucket in self.table.iter() {
+            for slot in bucket.slots.iter() {
+                slot.data.store(0, Ordering::Relaxed);
+                slot.key.store(0, Ordering::Relaxed);
             }
+        }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+
+    /// Permille of the table used by the current search, estimated from a sample of
+    /// the first buckets.
+    pub fn hashfull(&self) -> usize {
+        let buckets = &self.table[..min(self.table.len(), HASHFULL_SAMPLE / BUCKET_SIZE)];
+        let generation = self.generation();
+        let used = buckets
+            .iter()
+            .flat_map(|bucket| bucket.slots.iter())
+            .map(|slot| slot.data.load(Ordering::Relaxed))
+            .filter(|&data| data != 0 && age_of(data) == generation)
+            .count();
+        used * 1000 / (buckets.len() * BUCKET_SIZE)
+    }
+
+    /// Starts a new generation, making the entries of earlier searches the first to be
+    /// replaced.
+    pub fn new_search(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The generation new entries should be stored with.
+    pub fn generation(&self) -> u8 {
+        (self.generation.load(Ordering::Relaxed) % GENERATIONS as usize) as u8
+    }
+
+    /// Generations passed since an entry was stored.
+    fn relative_age(&self, data: u64) -> i32 {
+        ((GENERATIONS + self.generation() - age_of(data)) % GENERATIONS) as i32
+    }
+
+    /// Stores `entry` for a position `ply` plies from the root of the search, over the least
+    /// valuable entry of its bucket: entries of earlier searches go first, then shallow ones.
+    /// An entry for the same position is only replaced by a search at least as deep, unless
+    /// it is stale.
+    pub fn put(&self, entry: TEntry, ply: i32) {
+        let entry = TEntry {
+            value: entry.value.map(|score| score_to_table(score, ply)),
+            ..entry
         };
-        self.table[index].swap(boxed_entry, Ordering::Relaxed);
-    }
-
-    pub fn fetch(&self, hash: u64) -> Option<TEntry> {
-        let index = self.hash_index(hash);
-        let bucket = &self.table[index];
-        match bucket.take(Ordering::Relaxed) {
-            None => None,
-            Some(entry) => {
-                let result = if entry.hash == hash {
-                    Some(*entry)
-                } else {
-                    None
-                };
-                bucket.try_store(entry, Ordering::Relaxed);
-                result
+        let data = pack(&entry);
+        let bucket = &self.table[self.hash_index(entry.hash)];
+        let mut victim = 0;
+        let mut victim_worth = i32::max_value();
+        for (i, slot) in bucket.slots.iter().enumerate() {
+            if let Some(cur_data) = slot.load(entry.hash) {
+                if entry.depth < depth_of(cur_data) && self.relative_age(cur_data) == 0 {
+                    return;
+                }
+                victim = i;
+                break;
+            }
+            let cur_data = slot.data.load(Ordering::Relaxed);
+            if cur_data == 0 {
+                victim = i;
+                break;
+            }
+            let worth = depth_of(cur_data) - AGE_WEIGHT * self.relative_age(cur_data);
+            if worth < victim_worth {
+                victim = i;
+                victim_worth = worth;
             }
         }
+        bucket.slots[victim].store(entry.hash, data);
+    }
+
+    /// Looks up the entry for a position `ply` plies from the root of the search.
+    pub fn fetch(&self, hash: u64, ply: i32) -> Option<TEntry> {
+        let bucket = &self.table[self.hash_index(hash)];
+        bucket
+            .slots
+            .iter()
+            .filter_map(|slot| slot.load(hash))
+            .next()
+            .map(|data| {
+                let entry = unpack(hash, data);
+                TEntry {
+                    value: entry.value.map(|score| score_from_table(score, ply)),
+                    ..entry
+                }
+            })
     }
 
     fn hash_index(&self, hash: u64) -> usize {
         (hash % self.table.len() as u64) as usize
     }
 }
+
+/// Replaces `ttable` with a table of `capacity` bytes, dropping this reference to the old
+/// one before allocating. The old table is only freed once the searches still running on
+/// it drop their references too, so stop those first to keep both out of memory at once.
+pub fn resize_table(ttable: &mut Arc<TTable>, capacity: usize) {
+    drop(mem::replace(ttable, Arc::new(TTable::new(0))));
+    *ttable = Arc::new(TTable::new(capacity));
+}
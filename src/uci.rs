@@ -0,0 +1,371 @@
+use std::io::{self, BufRead};
+use std::str::SplitWhitespace;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chess::*;
+
+use eval::Score;
+use history::*;
+use limits::*;
+use minmax::*;
+use skill::*;
+use ttable::*;
+use utils::*;
+
+const MAX_MULTI_PV: usize = 256;
+const MAX_MARGIN: Score = 1000;
+
+/// Splits `setoption name <name> [value <value>]` into name and value.
+fn parse_setoption(tokens: SplitWhitespace) -> (String, String) {
+    let mut name = Vec::new();
+    let mut value = Vec::new();
+    let mut in_value = false;
+    for token in tokens {
+        match token {
+            "name" if !in_value && name.is_empty() => (),
+            "value" if !in_value => in_value = true,
+            _ if in_value => value.push(token),
+            _ => name.push(token),
+        }
+    }
+    (name.join(" "), value.join(" "))
+}
+
+fn set_margin(margin: &mut Score, value: &str) {
+    match value.parse() {
+        Ok(value) if value >= 0 && value <= MAX_MARGIN => *margin = value,
+        _ => println!("info string invalid margin {}", value),
+    }
+}
+
+fn parse_millis(token: Option<&str>) -> Option<Duration> {
+    token
+        .and_then(|t| t.parse().ok())
+        .map(Duration::from_millis)
+}
+
+/// Parses the arguments of `go` for a search from `board`.
+fn parse_go(tokens: SplitWhitespace, board: &Board) -> SearchLimits {
+    let mut tokens = tokens.peekable();
+    let mut limits = SearchLimits::default();
+    let (time_token, inc_token) = match board.side_to_move() {
+        Color::White => ("wtime", "winc"),
+        Color::Black => ("btime", "binc"),
+    };
+    while let Some(token) = tokens.next() {
+        match token {
+            "infinite" => limits.infinite = true,
+            "ponder" => limits.ponder = true,
+            "depth" => limits.depth = tokens.next().and_then(|t| t.parse().ok()),
+            "nodes" => limits.nodes = tokens.next().and_then(|t| t.parse().ok()),
+            "movestogo" => limits.moves_to_go = tokens.next().and_then(|t| t.parse().ok()),
+            "movetime" => limits.movetime = parse_millis(tokens.next()),
+            "searchmoves" => {
+                while let Some(cmove) = tokens.peek().and_then(|t| parse_coord_move(board, t)) {
+                    limits.search_moves.push(cmove);
+                    tokens.next();
+                }
+            }
+            t if t == time_token => limits.time_left = parse_millis(tokens.next()),
+            t if t == inc_token => {
+                limits.increment = parse_millis(tokens.next()).unwrap_or_default()
+            }
+            _ => (),
+        }
+    }
+    limits
+}
+
+fn print_info(update: &ISUpdate) {
+    let stats = &update.stats;
+    let millis = stats.elapsed.as_secs() * 1000 + stats.elapsed.subsec_millis() as u64;
+    for (i, line) in update.lines.iter().enumerate() {
+        let pv: Vec<String> = line.line.iter().map(|&m| move_to_coord(m)).collect();
+        let score = match line.mate {
+            Some(moves) => format!("mate {}", moves),
+            None => format!("cp {}", line.score),
+        };
+        println!(
+            "info depth {} seldepth {} multipv {} score {} nodes {} nps {} hashfull {} time {} pv {}",
+            update.depth,
+            stats.seldepth,
+            i + 1,
+            score,
+            stats.nodes,
+            stats.nps(),
+            update.hashfull,
+            millis,
+            pv.join(" ")
+        );
+    }
+    // The remaining counters have no info field of their own.
+    println!(
+        "info string qnodes {} ttprobes {} tthits {:.1}% ttcutoffs {} fhf {:.1}%",
+        stats.qnodes,
+        stats.tt_probes,
+        stats.tt_hit_rate(),
+        stats.tt_cutoffs,
+        stats.first_move_fail_high_rate()
+    );
+}
+
+/// Prints the first move of `line`, or any move allowed by `search_moves` without a line.
+fn print_bestmove(board: &Board, search_moves: &[ChessMove], line: &[ChessMove]) {
+    match line
+        .first()
+        .or_else(|| search_moves.first())
+        .cloned()
+        .or_else(|| generate_moves(board).first().cloned())
+    {
+        None => println!("bestmove 0000"),
+        Some(cmove) => match line.get(1) {
+            Some(&ponder) if line[0] == cmove => println!(
+                "bestmove {} ponder {}",
+                move_to_coord(cmove),
+                move_to_coord(ponder)
+            ),
+            _ => println!("bestmove {}", move_to_coord(cmove)),
+        },
+    }
+}
+
+fn parse_position(mut tokens: SplitWhitespace) -> Option<(Board, History)> {
+    let (board, halfmove_clock) = match tokens.next()? {
+        "startpos" => match tokens.next() {
+            None | Some("moves") => (Board::default(), 0),
+            Some(_) => return None,
+        },
+        "fen" => {
+            let fen: Vec<&str> = tokens.by_ref().take_while(|&t| t != "moves").collect();
+            let fen = fen.join(" ");
+            (Board::from_fen(fen.clone())?, fen_halfmove_clock(&fen))
+        }
+        _ => return None,
+    };
+    apply_moves(board, History::new(&board, halfmove_clock), tokens)
+}
+
+fn apply_moves(
+    mut board: Board,
+    mut history: History,
+    tokens: SplitWhitespace,
+) -> Option<(Board, History)> {
+    for token in tokens {
+        let cmove = parse_coord_move(&board, token)?;
+        history.push(&board, cmove);
+        board = board.make_move(cmove);
+    }
+    Some((board, history))
+}
+
+/// Runs the UCI protocol on stdin/stdout until `quit` or end of input.
+pub fn run(mut ttable: Arc<TTable>, mut options: SearchOptions) {
+    let stdin = io::stdin();
+    let mut board = Board::default();
+    let mut history = History::new(&board, 0);
+    let mut search: Option<InfiniteSearch> = None;
+    // UCI_LimitStrength chooses between the two ways to set the skill level.
+    let mut skill_level = options.skill_level;
+    let mut limit_strength = false;
+    let mut elo = MAX_ELO;
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let mut tokens = line.split_whitespace();
+        let command = match tokens.next() {
+            Some(command) => command,
+            None => continue,
+        };
+        match command {
+            "uci" => {
+                println!("id name rust_chess");
+                println!("id author Jonathan Heek");
+                println!(
+                    "option name Threads type spin default {} min 1 max {}",
+                    options.threads, MAX_THREADS
+                );
+                println!(
+                    "option name Hash type spin default {} min 1 max {}",
+                    ttable.capacity() / MB,
+                    MAX_HASH_MB
+                );
+                println!("option name Clear Hash type button");
+                println!(
+                    "option name MultiPV type spin default {} min 1 max {}",
+                    options.multi_pv, MAX_MULTI_PV
+                );
+                println!(
+                    "option name NullMove type check default {}",
+                    options.null_move
+                );
+                println!("option name Ponder type check default {}", options.ponder);
+                println!(
+                    "option name Contempt type spin default {} min {} max {}",
+                    options.contempt, -MAX_CONTEMPT, MAX_CONTEMPT
+                );
+                println!(
+                    "option name Skill Level type spin default {} min 0 max {}",
+                    skill_level, MAX_SKILL_LEVEL
+                );
+                println!(
+                    "option name UCI_LimitStrength type check default {}",
+                    limit_strength
+                );
+                println!(
+                    "option name UCI_Elo type spin default {} min {} max {}",
+                    elo, MIN_ELO, MAX_ELO
+                );
+                let strategies: Vec<String> = RootSearch::ALL
+                    .iter()
+                    .map(|strategy| format!("var {}", strategy.name()))
+                    .collect();
+                println!(
+                    "option name RootSearch type combo default {} {}",
+                    options.root_search.name(),
+                    strategies.join(" ")
+                );
+                for &(name, margin) in [
+                    ("FutilityMargin", options.futility_margin),
+                    ("ReverseFutilityMargin", options.reverse_futility_margin),
+                    ("RazorMargin", options.razor_margin),
+                ]
+                .iter()
+                {
+                    println!(
+                        "option name {} type spin default {} min 0 max {}",
+                        name, margin, MAX_MARGIN
+                    );
+                }
+                println!("uciok");
+            }
+            "isready" => println!("readyok"),
+            "setoption" => {
+                let (name, value) = parse_setoption(tokens);
+                match name.to_lowercase().as_str() {
+                    "threads" => match value.parse() {
+                        Ok(threads) if threads >= 1 && threads <= MAX_THREADS => {
+                            options.threads = threads
+                        }
+                        _ => println!("info string invalid thread count {}", value),
+                    },
+                    "hash" => match value.parse::<usize>() {
+                        Ok(megabytes) if megabytes >= 1 && megabytes <= MAX_HASH_MB => {
+                            if let Some(search) = search.take() {
+                                search.join();
+                            }
+                            resize_table(&mut ttable, megabytes * MB);
+                        }
+                        _ => println!("info string invalid hash size {}", value),
+                    },
+                    "clear hash" => ttable.clear(),
+                    "multipv" => match value.parse() {
+                        Ok(lines) if lines >= 1 && lines <= MAX_MULTI_PV => {
+                            options.multi_pv = lines
+                        }
+                        _ => println!("info string invalid MultiPV {}", value),
+                    },
+                    "nullmove" => match value.parse() {
+                        Ok(enabled) => options.null_move = enabled,
+                        _ => println!("info string invalid boolean {}", value),
+                    },
+                    "ponder" => match value.parse() {
+                        Ok(enabled) => options.ponder = enabled,
+                        _ => println!("info string invalid boolean {}", value),
+                    },
+                    "contempt" => match value.parse() {
+                        Ok(contempt) if contempt >= -MAX_CONTEMPT && contempt <= MAX_CONTEMPT => {
+                            options.contempt = contempt
+                        }
+                        _ => println!("info string invalid contempt {}", value),
+                    },
+                    "skill level" => match value.parse() {
+                        Ok(level) if level <= MAX_SKILL_LEVEL => skill_level = level,
+                        _ => println!("info string invalid skill level {}", value),
+                    },
+                    "uci_limitstrength" => match value.parse() {
+                        Ok(enabled) => limit_strength = enabled,
+                        _ => println!("info string invalid boolean {}", value),
+                    },
+                    "uci_elo" => match value.parse() {
+                        Ok(rating) if rating >= MIN_ELO && rating <= MAX_ELO => elo = rating,
+                        _ => println!("info string invalid Elo {}", value),
+                    },
+                    "rootsearch" => match RootSearch::from_name(&value) {
+                        Some(strategy) => options.root_search = strategy,
+                        None => println!("info string unknown root search {}", value),
+                    },
+                    "futilitymargin" => set_margin(&mut options.futility_margin, &value),
+                    "reversefutilitymargin" => {
+                        set_margin(&mut options.reverse_futility_margin, &value)
+                    }
+                    "razormargin" => set_margin(&mut options.razor_margin, &value),
+                    _ => println!("info string unknown option {}", name),
+                }
+                options.skill_level = if limit_strength {
+                    skill_level_for_elo(elo)
+                } else {
+                    skill_level
+                };
+            }
+            "ucinewgame" => {
+                if let Some(search) = search.take() {
+                    search.join();
+                }
+                board = Board::default();
+                history = History::new(&board, 0);
+                ttable.clear();
+            }
+            "position" => {
+                if let Some(search) = search.take() {
+                    search.join();
+                }
+                match parse_position(tokens) {
+                    Some((new_board, new_history)) => {
+                        board = new_board;
+                        history = new_history;
+                    }
+                    None => println!("info string invalid position: {}", line),
+                }
+            }
+            "go" => {
+                if let Some(search) = search.take() {
+                    search.join();
+                }
+                let limits = parse_go(tokens, &board);
+                let search_moves = limits.search_moves.clone();
+                search = Some(InfiniteSearch::start(
+                    ttable.clone(),
+                    board,
+                    history.clone(),
+                    limits,
+                    options.clone(),
+                    move |update| {
+                        if update.done {
+                            print_bestmove(&board, &search_moves, &update.line);
+                        } else {
+                            print_info(&update);
+                        }
+                    },
+                ));
+            }
+            "ponderhit" => {
+                if let Some(ref search) = search {
+                    search.ponder_hit();
+                }
+            }
+            "stop" => {
+                if let Some(search) = search.take() {
+                    search.join();
+                }
+            }
+            "quit" => break,
+            _ => println!("info string unknown command: {}", line),
+        }
+    }
+    if let Some(search) = search.take() {
+        search.join();
+    }
+}
@@ -7,3 +7,27 @@ pub fn generate_moves(board: &Board) -> Vec<ChessMove> {
     let num_moves = board.enumerate_moves(&mut moves);
     moves[..num_moves].iter().cloned().collect()
 }
+
+/// Formats a move in coordinate notation (e.g. `e2e4`, `e7e8q`).
+pub fn move_to_coord(cmove: ChessMove) -> String {
+    let mut s = format!("{}{}", cmove.get_source(), cmove.get_dest());
+    if let Some(piece) = cmove.get_promotion() {
+        s += &piece.to_string().to_lowercase();
+    }
+    s
+}
+
+/// Finds the legal move on `board` written in coordinate notation.
+pub fn parse_coord_move(board: &Board, text: &str) -> Option<ChessMove> {
+    generate_moves(board)
+        .into_iter()
+        .find(|&m| move_to_coord(m) == text)
+}
+
+/// The halfmove clock field of a FEN string, which `Board::from_fen` ignores.
+pub fn fen_halfmove_clock(fen: &str) -> u32 {
+    fen.split_whitespace()
+        .nth(4)
+        .and_then(|clock| clock.parse().ok())
+        .unwrap_or(0)
+}
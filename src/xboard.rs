@@ -0,0 +1,365 @@
+use std::cmp::{max, min};
+use std::io::{self, BufRead};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread::spawn;
+use std::time::Duration;
+
+use chess::*;
+
+use eval::*;
+use history::*;
+use limits::*;
+use minmax::*;
+use ttable::*;
+use utils::*;
+
+enum Event {
+    Input(String),
+    Done(u32, Option<ChessMove>),
+}
+
+fn print_thinking(update: &ISUpdate) {
+    let elapsed = update.stats.elapsed;
+    let centis = elapsed.as_secs() * 100 + elapsed.subsec_millis() as u64 / 10;
+    let pv: Vec<String> = update.line.iter().map(|&m| move_to_coord(m)).collect();
+    // XBoard shows mate in N for scores of 100000 + N.
+    let score = match update.mate {
+        Some(moves) if moves >= 0 => 100000 + moves,
+        Some(moves) => -100000 + moves,
+        None => update.score,
+    };
+    println!(
+        "{} {} {} {} {}",
+        update.depth,
+        score,
+        centis,
+        update.stats.nodes,
+        pv.join(" ")
+    );
+}
+
+struct XBoard {
+    board: Board,
+    history: History,
+    /// The positions before each move, for `undo`.
+    boards: Vec<Board>,
+    force: bool,
+    engine_color: Color,
+    post: bool,
+    max_depth: i32,
+    moves_per_session: u64,
+    increment: u64,
+    move_time: Option<u64>,
+    time_left: Option<u64>,
+    ttable: Arc<TTable>,
+    options: SearchOptions,
+    search: Option<InfiniteSearch>,
+    search_id: u32,
+}
+
+impl XBoard {
+    fn new(ttable: Arc<TTable>, options: SearchOptions) -> XBoard {
+        XBoard {
+            board: Board::default(),
+            history: History::new(&Board::default(), 0),
+            boards: Vec::new(),
+            force: false,
+            engine_color: Color::Black,
+            post: false,
+            max_depth: MAX_DEPTH,
+            moves_per_session: 0,
+            increment: 0,
+            move_time: None,
+            time_left: None,
+            ttable,
+            options,
+            search: None,
+            search_id: 0,
+        }
+    }
+
+    /// Stops the current search and discards its result.
+    fn abort_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            search.join();
+        }
+        self.search_id += 1;
+    }
+
+    fn limits(&self) -> SearchLimits {
+        let moves_to_go = if self.moves_per_session > 0 {
+            let played = self.boards.len() as u64 / 2;
+            Some((self.moves_per_session - played % self.moves_per_session) as u32)
+        } else {
+            None
+        };
+        SearchLimits {
+            depth: Some(self.max_depth),
+            movetime: self.move_time.map(Duration::from_millis),
+            time_left: self.time_left.map(Duration::from_millis),
+            increment: Duration::from_millis(self.increment),
+            moves_to_go,
+            ..SearchLimits::default()
+        }
+    }
+
+    fn play(&mut self, cmove: ChessMove) {
+        self.boards.push(self.board);
+        self.history.push(&self.board, cmove);
+        self.board = self.board.make_move(cmove);
+    }
+
+    fn undo(&mut self) {
+        self.abort_search();
+        if let Some(board) = self.boards.pop() {
+            self.board = board;
+            self.history.pop();
+        }
+    }
+
+    fn reset(&mut self, board: Board, halfmove_clock: u32) {
+        self.board = board;
+        self.history = History::new(&board, halfmove_clock);
+        self.boards.clear();
+    }
+
+    /// The result line of the game if it has ended.
+    fn game_result(&self) -> Option<&'static str> {
+        let num_moves = generate_moves(&self.board).len();
+        match quick_status(&self.board, num_moves) {
+            BoardStatus::Ongoing => match self.history.draw_reason(&self.board) {
+                Some(DrawReason::Repetition) => Some("1/2-1/2 {Draw by repetition}"),
+                Some(DrawReason::FiftyMoves) => Some("1/2-1/2 {Draw by fifty move rule}"),
+                None => None,
+            },
+            BoardStatus::Checkmate => match self.board.side_to_move() {
+                Color::White => Some("0-1 {Black mates}"),
+                Color::Black => Some("1-0 {White mates}"),
+            },
+            BoardStatus::Stalemate => Some("1/2-1/2 {Stalemate}"),
+        }
+    }
+
+    /// Plays `cmove` and prints the result if it ends the game.
+    fn play_and_check(&mut self, cmove: ChessMove) {
+        self.play(cmove);
+        if let Some(result) = self.game_result() {
+            println!("{}", result);
+        }
+    }
+
+    fn maybe_think(&mut self, events: &Sender<Event>) {
+        if self.force
+            || self.search.is_some()
+            || self.board.side_to_move() != self.engine_color
+            || self.game_result().is_some()
+        {
+            return;
+        }
+        let board = self.board;
+        let history = self.history.clone();
+        let post = self.post;
+        let id = self.search_id;
+        let events = events.clone();
+        let limits = self.limits();
+        let options = self.options.clone();
+        let search = InfiniteSearch::start(
+            self.ttable.clone(),
+            board,
+            history,
+            limits,
+            options,
+            move |update| {
+                if update.done {
+                    let best = update
+                        .line
+                        .first()
+                        .cloned()
+                        .or_else(|| generate_moves(&board).first().cloned());
+                    let _ = events.send(Event::Done(id, best));
+                } else if post {
+                    print_thinking(&update);
+                }
+            },
+        );
+        self.search = Some(search);
+    }
+
+    fn handle_done(&mut self, id: u32, best: Option<ChessMove>) {
+        if id != self.search_id {
+            return;
+        }
+        if let Some(search) = self.search.take() {
+            search.join();
+        }
+        self.search_id += 1;
+        if let Some(cmove) = best {
+            println!("move {}", move_to_coord(cmove));
+            self.play_and_check(cmove);
+        }
+    }
+
+    /// Handles a single command; returns false on `quit`.
+    fn handle_input(&mut self, events: &Sender<Event>, line: &str) -> bool {
+        let mut tokens = line.split_whitespace();
+        let command = match tokens.next() {
+            Some(command) => command,
+            None => return true,
+        };
+        match command {
+            "xboard" | "accepted" | "rejected" | "random" | "computer" | "hard" | "easy"
+            | "result" | "otim" => (),
+            "protover" => {
+                println!(
+                    "feature myname=\"rust_chess\" usermove=1 setboard=1 ping=1 colors=0 smp=1 memory=1 \
+                     sigint=0 sigterm=0 done=1"
+                );
+            }
+            "new" => {
+                self.abort_search();
+                self.reset(Board::default(), 0);
+                self.ttable.clear();
+                self.force = false;
+                self.engine_color = Color::Black;
+                self.max_depth = MAX_DEPTH;
+                self.move_time = None;
+            }
+            "setboard" => {
+                self.abort_search();
+                let fen: Vec<&str> = tokens.collect();
+                let fen = fen.join(" ");
+                match Board::from_fen(fen.clone()) {
+                    Some(board) => self.reset(board, fen_halfmove_clock(&fen)),
+                    None => println!("tellusererror Illegal position"),
+                }
+            }
+            "force" => {
+                self.abort_search();
+                self.force = true;
+            }
+            "go" => {
+                self.force = false;
+                self.engine_color = self.board.side_to_move();
+            }
+            "playother" => {
+                self.force = false;
+                self.engine_color = !self.board.side_to_move();
+            }
+            "usermove" => {
+                let text = tokens.next().unwrap_or("");
+                match parse_coord_move(&self.board, text) {
+                    Some(cmove) => {
+                        self.abort_search();
+                        self.play_and_check(cmove);
+                    }
+                    None => println!("Illegal move: {}", text),
+                }
+            }
+            "undo" => {
+                // Thinking resumes with the next command rather than on the retracted move.
+                self.undo();
+                return true;
+            }
+            "remove" => {
+                self.undo();
+                self.undo();
+            }
+            "?" => {
+                if let Some(ref search) = self.search {
+                    search.stop();
+                }
+            }
+            "level" => {
+                let args: Vec<&str> = tokens.collect();
+                if args.len() == 3 {
+                    self.moves_per_session = args[0].parse().unwrap_or(0);
+                    let mut base = args[1].split(':');
+                    let minutes: u64 = base.next().and_then(|m| m.parse().ok()).unwrap_or(0);
+                    let seconds: u64 = base.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+                    self.time_left = Some((minutes * 60 + seconds) * 1000);
+                    self.increment = args[2]
+                        .parse::<f64>()
+                        .map(|i| (i * 1000.0) as u64)
+                        .unwrap_or(0);
+                    self.move_time = None;
+                }
+            }
+            "st" => {
+                self.move_time = tokens
+                    .next()
+                    .and_then(|t| t.parse::<u64>().ok())
+                    .map(|s| s * 1000);
+            }
+            "sd" => {
+                self.max_depth = tokens
+                    .next()
+                    .and_then(|t| t.parse().ok())
+                    .unwrap_or(MAX_DEPTH);
+            }
+            "time" => {
+                self.time_left = tokens
+                    .next()
+                    .and_then(|t| t.parse::<u64>().ok())
+                    .map(|cs| cs * 10);
+            }
+            "memory" => {
+                if let Some(megabytes) = tokens.next().and_then(|t| t.parse::<usize>().ok()) {
+                    self.abort_search();
+                    resize_table(&mut self.ttable, min(max(1, megabytes), MAX_HASH_MB) * MB);
+                }
+            }
+            "cores" => {
+                if let Some(threads) = tokens.next().and_then(|t| t.parse().ok()) {
+                    self.options.threads = min(max(1, threads), MAX_THREADS);
+                }
+            }
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "ping" => println!("pong {}", tokens.next().unwrap_or("")),
+            "quit" => return false,
+            _ => match parse_coord_move(&self.board, command) {
+                // Engines that did not get usermove=1 accepted receive bare moves.
+                Some(cmove) => {
+                    self.abort_search();
+                    self.play_and_check(cmove);
+                }
+                None => println!("Error (unknown command): {}", command),
+            },
+        }
+        self.maybe_think(events);
+        true
+    }
+}
+
+/// Runs the xboard protocol on stdin/stdout until `quit` or end of input.
+pub fn run(ttable: Arc<TTable>, options: SearchOptions) {
+    let (events, receiver) = channel();
+    let input = events.clone();
+    spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => {
+                    if input.send(Event::Input(line)).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        let _ = input.send(Event::Input("quit".to_string()));
+    });
+    let mut xboard = XBoard::new(ttable, options);
+    for event in receiver.iter() {
+        match event {
+            Event::Input(line) => {
+                if !xboard.handle_input(&events, &line) {
+                    break;
+                }
+            }
+            Event::Done(id, best) => xboard.handle_done(id, best),
+        }
+    }
+    xboard.abort_search();
+}
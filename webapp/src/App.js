@@ -59,6 +59,10 @@ class App extends Component {
       lineup: msg.lineup,
       bestLine: msg.best_line,
       bestValue: msg.best_value,
+      bestMate: msg.best_mate,
+      bestLines: msg.best_lines,
+      status: msg.status,
+      hanging: msg.hanging,
     }));
   }
 
@@ -111,7 +115,9 @@ class App extends Component {
     if (line.length > 0) {
       focusTiles = [line[0].from, line[0].to];
       let lineTxt = line.map(({from, to}) => `${from}-${to}`).join(" ")
-      bestLineTxt = `${this.state.bestValue} ${line.length} ${lineTxt}`;
+      let mate = this.state.bestMate;
+      let valueTxt = mate == null ? this.state.bestValue : `#${mate}`;
+      bestLineTxt = `${valueTxt} ${line.length} ${lineTxt}`;
     }
     console.log("bestLineTxt", bestLineTxt)
     return (
@@ -122,6 +128,15 @@ class App extends Component {
         <pre style={{lineHeight: 1}}>
           {bestLineTxt}
         </pre>
+        {this.state.bestLines && this.state.bestLines.length > 1 &&
+          <pre style={{lineHeight: 1}}>
+            {this.state.bestLines.map(({line, value, mate}) => {
+              let valueTxt = mate == null ? value : `#${mate}`;
+              return `${valueTxt} ${line.map(({from, to}) => `${from}-${to}`).join(" ")}`;
+            }).join("\n")}
+          </pre>}
+        {this.state.status && this.state.status != "ongoing" && <p>Game over: {this.state.status}</p>}
+        {this.state.hanging && this.state.hanging.length > 0 && <p>Hanging: {this.state.hanging.join(" ")}</p>}
         <form>
           <label>
             <input